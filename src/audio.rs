@@ -1,25 +1,200 @@
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    tasks::IoTaskPool,
+};
 
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{FromSample, SizedSample};
 use fundsp::hacker32::*;
 
-use crossbeam_channel::{bounded, Sender};
+use crossbeam_channel::{bounded, Sender, Receiver};
 
-use crate::components::*;
+use std::{
+    sync::{Arc, atomic::{AtomicBool, AtomicUsize, Ordering}},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    components::*,
+    nodes::InputBuffer,
+    cli::Cli,
+    config::Prefs,
+};
+
+// the part of the recorder that lives in the output callback
+#[derive(Clone)]
+pub struct RecTap {
+    pub on: Arc<AtomicBool>,
+    pub sender: Sender<f32>,
+    pub dropped: Arc<AtomicUsize>,
+}
+
+#[derive(Resource)]
+pub struct Recorder {
+    pub tap: RecTap,
+    pub receiver: Receiver<f32>,
+    pub writer: Option<JoinHandle<()>>,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        // ~6 seconds of stereo at 44100
+        let (sender, receiver) = bounded(1 << 19);
+        Recorder {
+            tap: RecTap {
+                on: Arc::new(AtomicBool::new(false)),
+                sender,
+                dropped: Arc::new(AtomicUsize::new(0)),
+            },
+            receiver,
+            writer: None,
+        }
+    }
+}
+
+impl Recorder {
+    pub fn start(&mut self, path: &str, sample_rate: u32, channels: u16) {
+        self.stop();
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        match hound::WavWriter::create(path, spec) {
+            Ok(mut writer) => {
+                // leftovers from the end of the last take
+                while self.receiver.try_recv().is_ok() {}
+                self.tap.dropped.store(0, Ordering::Relaxed);
+                let receiver = self.receiver.clone();
+                let on = self.tap.on.clone();
+                let dropped = self.tap.dropped.clone();
+                let path = path.to_string();
+                on.store(true, Ordering::Relaxed);
+                // the writer polls instead of blocking on recv, that way
+                // the callback never has to wake it up (no locks there)
+                self.writer = Some(thread::spawn(move || {
+                    loop {
+                        let recording = on.load(Ordering::Relaxed);
+                        for sample in receiver.try_iter() {
+                            let _ = writer.write_sample(sample);
+                        }
+                        if !recording { break; }
+                        thread::sleep(Duration::from_millis(10));
+                    }
+                    if let Err(e) = writer.finalize() {
+                        error!("couldn't finish writing {}: {}", path, e);
+                    }
+                    let dropped = dropped.load(Ordering::Relaxed);
+                    if dropped > 0 {
+                        warn!("{} frames were dropped while recording {}", dropped, path);
+                    }
+                    info!("stopped recording {}", path);
+                }));
+            }
+            Err(e) => error!("couldn't create {}: {}", path, e),
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.tap.on.store(false, Ordering::Relaxed);
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+// start recording to the given file, or stop if there's none
+pub fn record(
+    mut record_events: EventReader<RecordCommand>,
+    mut recorder: ResMut<Recorder>,
+    sample_rate: Res<SampleRate>,
+    out_channels: Res<OutChannels>,
+) {
+    for RecordCommand(file) in record_events.read() {
+        if let Some(file) = file {
+            recorder.start(file, sample_rate.0 as u32, out_channels.0 as u16);
+        } else {
+            recorder.stop();
+        }
+    }
+}
+
+// mix the nets of all out() circles into one net with a channel
+// for every device channel, and send that to the slot
+pub fn mix_outputs(
+    mut dac_change: EventReader<DacChange>,
+    mut out_nets: ResMut<OutNets>,
+    mut out_net: ResMut<OutNet>,
+    mut slot: ResMut<SlotRes>,
+    out_channels: Res<OutChannels>,
+    sample_rate: Res<SampleRate>,
+    op_num_query: Query<&OpNum>,
+    changed_op_num: Query<(Entity, &OpNum), Changed<OpNum>>,
+    mut removed_op_num: RemovedComponents<OpNum>,
+) {
+    // only out() circles (and ones that just stopped being out()) matter,
+    // rebuilding restarts every output
+    let is_out = |e: &Entity| out_nets.0.iter().any(|(x, _, _)| x == e);
+    let removed = removed_op_num.read().filter(is_out).count() > 0;
+    let changed = changed_op_num.iter().any(|(e, n)| n.0 == 90 || is_out(&e));
+    if dac_change.is_empty() && !removed && !changed
+    && !slot.is_changed() && !sample_rate.is_changed() && !out_channels.is_changed() {
+        return;
+    }
+    dac_change.clear();
+    // forget deleted circles and ones that aren't out() anymore
+    out_nets.0.retain(|(e, _, _)| op_num_query.get(*e).is_ok_and(|n| n.0 == 90));
+    let channels = out_channels.0;
+    let mut master = Net::new(0, channels);
+    for (_, offset, net) in &out_nets.0 {
+        if *offset >= channels { continue; }
+        let outs = net.outputs().min(channels - offset);
+        let mut net = net.clone();
+        // drop the outputs that don't fit
+        if net.outputs() > outs {
+            let mut router = Net::new(0, 0);
+            for i in 0..net.outputs() {
+                if i < outs {
+                    router = router | Net::wrap(Box::new(pass()));
+                } else {
+                    router = router | Net::wrap(Box::new(sink()));
+                }
+            }
+            net = net >> router;
+        }
+        master = master + (Net::new(0, *offset) | net | Net::new(0, channels - offset - outs));
+    }
+    master.set_sample_rate(sample_rate.0);
+    slot.0.set(Fade::Smooth, 0.01, Box::new(master.clone()));
+    out_net.0 = master;
+}
 
 pub fn default_out_device(world: &mut World) {
-    let slot = Slot::new(Box::new(dc(0.) | dc(0.)));
+    let cli = world.get_resource::<Cli>().cloned().unwrap_or_default();
+    let channels = cli.channels.unwrap_or(2).max(1);
+    let slot = Slot::new(Box::new(Net::new(0, channels as usize)));
     world.insert_resource(SlotRes(slot.0));
+    world.insert_resource(OutChannels(channels as usize));
+    // device given in the command line
+    if let Some((h, d)) = cli.out_device {
+        world.send_event(OutDeviceCommand(h, d, cli.sample_rate, cli.buffer_size, cli.channels));
+        return;
+    }
     let host = cpal::default_host();
     if let Some(device) = host.default_output_device() {
         let default_config = device.default_output_config().unwrap();
         let mut config = default_config.config();
-        config.channels = 2;
+        config.channels = channels;
+        if let Some(sr) = cli.sample_rate { config.sample_rate = cpal::SampleRate(sr); }
+        if let Some(b) = cli.buffer_size { config.buffer_size = cpal::BufferSize::Fixed(b); }
+        world.insert_resource(SampleRate(config.sample_rate.0 as f64));
+        let tap = world.resource::<Recorder>().tap.clone();
         let stream = match default_config.sample_format() {
-            cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), slot.1),
-            cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), slot.1),
-            cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), slot.1),
+            cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), slot.1, tap),
+            cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), slot.1, tap),
+            cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), slot.1, tap),
             format => {
                 error!("unsupported sample format: {}", format);
                 None
@@ -37,22 +212,29 @@ pub fn set_out_device(world: &mut World) {
     let mut out_events = world.resource_mut::<Events<OutDeviceCommand>>();
     let events: Vec<OutDeviceCommand> = out_events.drain().collect();
     for e in events {
-        let slot = Slot::new(Box::new(dc(0.) | dc(0.)));
+        let OutDeviceCommand(h, d, sr, b, ch) = e;
+        let channels = ch.unwrap_or(2).max(1);
+        let slot = Slot::new(Box::new(Net::new(0, channels as usize)));
         world.insert_resource(SlotRes(slot.0));
-        let OutDeviceCommand(h, d, sr, b) = e;
+        world.insert_resource(OutChannels(channels as usize));
         if let Some(host_id) = cpal::platform::ALL_HOSTS.get(h) {
             if let Ok(host) = cpal::platform::host_from_id(*host_id) {
                 if let Ok(mut devices) = host.output_devices() {
                     if let Some(device) = devices.nth(d) {
                         let default_config = device.default_output_config().unwrap();
                         let mut config = default_config.config();
-                        config.channels = 2;
+                        config.channels = channels;
                         if let Some(sr) = sr { config.sample_rate = cpal::SampleRate(sr); }
                         if let Some(b) = b { config.buffer_size = cpal::BufferSize::Fixed(b); }
+                        // the file header won't match the new stream
+                        let mut recorder = world.resource_mut::<Recorder>();
+                        recorder.stop();
+                        let tap = recorder.tap.clone();
+                        world.insert_resource(SampleRate(config.sample_rate.0 as f64));
                         let stream = match default_config.sample_format() {
-                            cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), slot.1),
-                            cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), slot.1),
-                            cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), slot.1),
+                            cpal::SampleFormat::F32 => run::<f32>(&device, &config.into(), slot.1, tap),
+                            cpal::SampleFormat::I16 => run::<i16>(&device, &config.into(), slot.1, tap),
+                            cpal::SampleFormat::U16 => run::<u16>(&device, &config.into(), slot.1, tap),
                             format => {
                                 error!("unsupported sample format: {}", format);
                                 None
@@ -60,6 +242,13 @@ pub fn set_out_device(world: &mut World) {
                         };
                         if let Some(stream) = stream {
                             world.insert_non_send_resource(OutStream(stream));
+                            // remembered for :saveprefs
+                            if let Some(mut prefs) = world.get_resource_mut::<Prefs>() {
+                                prefs.out_device = Some((h, d));
+                                prefs.sample_rate = sr.or(prefs.sample_rate);
+                                prefs.buffer_size = b.or(prefs.buffer_size);
+                                prefs.channels = ch.or(prefs.channels);
+                            }
                         } else {
                             error!("couldn't build stream");
                         }
@@ -74,23 +263,25 @@ fn run<T>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
     slot: SlotBackend,
+    tap: RecTap,
 ) -> Option<cpal::Stream> where
     T: SizedSample + FromSample<f32>,
 {
     let mut slot = BlockRateAdapter::new(Box::new(slot));
+    slot.set_sample_rate(config.sample_rate.0 as f64);
 
-    let mut next_value = move || {
-        let (l, r) = slot.get_stereo();
-        (
-            if l.is_normal() { l.clamp(-1., 1.) } else { 0. },
-            if r.is_normal() { r.clamp(-1., 1.) } else { 0. },
-        )
+    let mut next_frame = move |frame: &mut [f32]| {
+        slot.tick(&[], frame);
+        for sample in frame.iter_mut() {
+            *sample = if sample.is_normal() { sample.clamp(-1., 1.) } else { 0. };
+        }
     };
+    let mut buffer = vec![0.; config.channels as usize];
     let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
     let stream = device.build_output_stream(
         config,
         move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_data(data, &mut next_value)
+            write_data(data, &mut buffer, &mut next_frame, &tap)
         },
         err_fn,
         None,
@@ -103,30 +294,96 @@ fn run<T>(
     None
 }
 
-fn write_data<T>(output: &mut [T], next_sample: &mut dyn FnMut() -> (f32, f32))
-where
+fn write_data<T>(
+    output: &mut [T],
+    buffer: &mut [f32],
+    next_frame: &mut dyn FnMut(&mut [f32]),
+    tap: &RecTap,
+) where
     T: SizedSample + FromSample<f32>,
 {
-    for frame in output.chunks_mut(2) {
-        let sample = next_sample();
-        frame[0] = T::from_sample(sample.0);
-        frame[1] = T::from_sample(sample.1);
+    let recording = tap.on.load(Ordering::Relaxed);
+    for frame in output.chunks_mut(buffer.len()) {
+        next_frame(buffer);
+        for (out, sample) in frame.iter_mut().zip(buffer.iter()) {
+            *out = T::from_sample(*sample);
+        }
+        if recording {
+            // never wait on the writer, drop the whole frame if it's behind
+            let space = tap.sender.capacity().unwrap_or(0) - tap.sender.len();
+            if space >= buffer.len() {
+                for sample in buffer.iter() {
+                    let _ = tap.sender.try_send(*sample);
+                }
+            } else {
+                tap.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
     }
 }
 
 
+// render the out() net to a wav file, faster than realtime
+pub fn render_out(
+    mut render_events: EventReader<RenderCommand>,
+    out_net: Res<OutNet>,
+    sample_rate: Res<SampleRate>,
+    status: Res<StatusChannel>,
+) {
+    for RenderCommand(file, dur, sr, bits) in render_events.read() {
+        let bits = bits.unwrap_or(32);
+        if bits != 16 && bits != 32 {
+            let _ = status.0.0.send(format!("can't render {} bit wav, use 16 or 32", bits));
+            continue;
+        }
+        let mut net = out_net.0.clone();
+        let file = file.clone();
+        let dur = dur.max(0.) as f64;
+        let sr = sr.map_or(sample_rate.0, |sr| sr as f64);
+        #[cfg(not(target_arch = "wasm32"))]
+        IoTaskPool::get()
+            .spawn(async move {
+                net.set_sample_rate(sr);
+                net.reset();
+                let wave = Wave::render(sr, dur, &mut net);
+                let result = if bits == 16 {
+                    wave.save_wav16(&file)
+                } else {
+                    wave.save_wav32(&file)
+                };
+                if let Err(e) = result {
+                    error!("couldn't write {}: {}", file, e);
+                } else {
+                    info!("rendered {} seconds to {}", dur, file);
+                }
+            })
+            .detach();
+    }
+}
 
 pub fn default_in_device(world: &mut World) {
-    let (ls, lr) = bounded(64);
-    let (rs, rr) = bounded(64);
-    world.insert_resource(InputReceivers(lr, rr));
+    world.insert_resource(InputBufferRes(Arc::new(InputBuffer::new(0, 44100., false))));
+    let cli = world.get_resource::<Cli>().cloned().unwrap_or_default();
+    if let Some((h, d)) = cli.in_device {
+        world.send_event(InDeviceCommand(h, d, cli.sample_rate, cli.buffer_size, None));
+        return;
+    }
     let host = cpal::default_host();
     if let Some(device) = host.default_input_device() {
-        let config = device.default_input_config().unwrap();
-        let stream = match config.sample_format() {
-            cpal::SampleFormat::F32 => run_in::<f32>(&device, &config.into(), ls, rs),
-            cpal::SampleFormat::I16 => run_in::<i16>(&device, &config.into(), ls, rs),
-            cpal::SampleFormat::U16 => run_in::<u16>(&device, &config.into(), ls, rs),
+        let default_config = device.default_input_config().unwrap();
+        let mut config = default_config.config();
+        if let Some(sr) = cli.sample_rate { config.sample_rate = cpal::SampleRate(sr); }
+        if let Some(b) = cli.buffer_size { config.buffer_size = cpal::BufferSize::Fixed(b); }
+        let buffer = Arc::new(InputBuffer::new(
+            config.channels as usize,
+            config.sample_rate.0 as f64,
+            false,
+        ));
+        world.insert_resource(InputBufferRes(buffer.clone()));
+        let stream = match default_config.sample_format() {
+            cpal::SampleFormat::F32 => run_in::<f32>(&device, &config.into(), buffer),
+            cpal::SampleFormat::I16 => run_in::<i16>(&device, &config.into(), buffer),
+            cpal::SampleFormat::U16 => run_in::<u16>(&device, &config.into(), buffer),
             format => {
                 error!("unsupported sample format: {}", format);
                 None
@@ -137,6 +394,9 @@ pub fn default_in_device(world: &mut World) {
         } else {
             error!("couldn't build stream");
         }
+    } else {
+        // the empty buffer stays, in() circles are silent
+        info!("no input device");
     }
 }
 
@@ -144,10 +404,9 @@ pub fn set_in_device(world: &mut World) {
     let mut out_events = world.resource_mut::<Events<InDeviceCommand>>();
     let events: Vec<InDeviceCommand> = out_events.drain().collect();
     for e in events {
-        let (ls, lr) = bounded(64);
-        let (rs, rr) = bounded(64);
-        world.insert_resource(InputReceivers(lr, rr));
-        let InDeviceCommand(h, d, sr, b) = e;
+        let adaptive = world.resource::<InputBufferRes>().0.adaptive.load(Ordering::Relaxed);
+        world.insert_resource(InputBufferRes(Arc::new(InputBuffer::new(0, 44100., adaptive))));
+        let InDeviceCommand(h, d, sr, b, ch) = e;
         if let Some(host_id) = cpal::platform::ALL_HOSTS.get(h) {
             if let Ok(host) = cpal::platform::host_from_id(*host_id) {
                 if let Ok(mut devices) = host.input_devices() {
@@ -156,10 +415,17 @@ pub fn set_in_device(world: &mut World) {
                         let mut config = default_config.config();
                         if let Some(sr) = sr { config.sample_rate = cpal::SampleRate(sr); }
                         if let Some(b) = b { config.buffer_size = cpal::BufferSize::Fixed(b); }
+                        if let Some(ch) = ch { config.channels = ch.max(1); }
+                        let buffer = Arc::new(InputBuffer::new(
+                            config.channels as usize,
+                            config.sample_rate.0 as f64,
+                            adaptive,
+                        ));
+                        world.insert_resource(InputBufferRes(buffer.clone()));
                         let stream = match default_config.sample_format() {
-                            cpal::SampleFormat::F32 => run_in::<f32>(&device, &config.into(), ls, rs),
-                            cpal::SampleFormat::I16 => run_in::<i16>(&device, &config.into(), ls, rs),
-                            cpal::SampleFormat::U16 => run_in::<u16>(&device, &config.into(), ls, rs),
+                            cpal::SampleFormat::F32 => run_in::<f32>(&device, &config.into(), buffer),
+                            cpal::SampleFormat::I16 => run_in::<i16>(&device, &config.into(), buffer),
+                            cpal::SampleFormat::U16 => run_in::<u16>(&device, &config.into(), buffer),
                             format => {
                                 error!("unsupported sample format: {}", format);
                                 None
@@ -167,6 +433,9 @@ pub fn set_in_device(world: &mut World) {
                         };
                         if let Some(stream) = stream {
                             world.insert_non_send_resource(InStream(stream));
+                            if let Some(mut prefs) = world.get_resource_mut::<Prefs>() {
+                                prefs.in_device = Some((h, d));
+                            }
                         } else {
                             error!("couldn't build stream");
                         }
@@ -180,17 +449,15 @@ pub fn set_in_device(world: &mut World) {
 fn run_in<T>(
     device: &cpal::Device,
     config: &cpal::StreamConfig,
-    ls: Sender<f32>,
-    rs: Sender<f32>,
+    buffer: Arc<InputBuffer>,
 ) -> Option<cpal::Stream> where
     T: SizedSample, f32: FromSample<T>
 {
-    let channels = config.channels as usize;
     let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
     let stream = device.build_input_stream(
         config,
         move |data: &[T], _: &cpal::InputCallbackInfo| {
-            read_data(data, channels, ls.clone(), rs.clone())
+            read_data(data, &buffer)
         },
         err_fn,
         None,
@@ -203,17 +470,11 @@ fn run_in<T>(
     None
 }
 
-fn read_data<T>(input: &[T], channels: usize, ls: Sender<f32>, rs: Sender<f32>)
+fn read_data<T>(input: &[T], buffer: &InputBuffer)
 where
     T: SizedSample, f32: FromSample<T>
 {
-    for frame in input.chunks(channels) {
-        for (channel, sample) in frame.iter().enumerate() {
-            if channel & 1 == 0 {
-                let _ = ls.send(sample.to_sample::<f32>());
-            } else {
-                let _ = rs.send(sample.to_sample::<f32>());
-            }
-        }
+    for frame in input.chunks(buffer.channels()) {
+        buffer.push(frame.iter().map(|s| s.to_sample::<f32>()));
     }
 }
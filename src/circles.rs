@@ -3,6 +3,7 @@ use bevy::{
     render::view::{RenderLayers, VisibleEntities},
     sprite::{Mesh2dHandle, WithMesh2d},
     text::Text2dBounds,
+    utils::HashMap,
 };
 
 use fundsp::net::Net;
@@ -125,6 +126,58 @@ pub fn transform_highlights(
     }
 }
 
+// a small triangle on circles that read from circles with an equal or higher order
+pub fn update_order_flags(
+    mut commands: Commands,
+    late: Res<LateCircles>,
+    circle_query: Query<(&Transform, &RenderLayers, &Visibility), With<Order>>,
+    mut flag_query: Query<(&mut Transform, &mut Visibility), Without<Order>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut polygon_handles: ResMut<PolygonHandles>,
+    mut flags: Local<HashMap<Entity, Entity>>,
+) {
+    flags.retain(|e, flag| {
+        if late.0.contains(e) && circle_query.contains(*e) { return true; }
+        if let Some(flag) = commands.get_entity(*flag) { flag.despawn(); }
+        false
+    });
+    for e in &late.0 {
+        let Ok((t, layers, vis)) = circle_query.get(*e) else { continue };
+        let trans = Transform {
+            translation: (t.translation.xy() + Vec2::new(-0.7, 0.7) * t.scale.x)
+                .extend(t.translation.z + 0.00001),
+            scale: Vec3::new(t.scale.x * 0.25, t.scale.x * 0.25, 1.),
+            ..default()
+        };
+        if let Some(flag) = flags.get(e) {
+            if let Ok((mut flag_t, mut flag_vis)) = flag_query.get_mut(*flag) {
+                *flag_t = trans;
+                *flag_vis = *vis;
+            }
+            continue;
+        }
+        if polygon_handles.0.len() <= 3 {
+            polygon_handles.0.resize(4, None);
+        }
+        if polygon_handles.0[3].is_none() {
+            let handle = meshes.add(RegularPolygon::new(1., 3)).into();
+            polygon_handles.0[3] = Some(handle);
+        }
+        let flag = commands.spawn((
+            ColorMesh2dBundle {
+                mesh: polygon_handles.0[3].clone().unwrap(),
+                material: materials.add(ColorMaterial::from_color(Hsla::new(0., 1., 0.5, 1.))),
+                transform: trans,
+                visibility: *vis,
+                ..default()
+            },
+            layers.clone(),
+        )).id();
+        flags.insert(*e, flag);
+    }
+}
+
 pub fn update_selection(
     mut commands: Commands,
     mouse_button_input: Res<ButtonInput<MouseButton>>,
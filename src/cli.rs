@@ -0,0 +1,164 @@
+use bevy::{
+    prelude::*,
+    asset::LoadState,
+};
+
+use crate::{components::*, osc::*, functions::scene_path};
+
+const USAGE: &str = "usage: quartz [scene] [options]
+    scene                   scene file to open (in the assets folder, unless it starts with / ./ ../ or ~/)
+    --headless              run without a window
+    --out {host} {device}   output device (host and device index, see `ah` and `ao`)
+    --in {host} {device}    input device (see `ah` and `ai`)
+    --sr {rate}             sample rate
+    --buffer {size}         buffer size
+    --channels {n}          number of output channels
+    --osc {port}            osc receive port
+    --cmd {commands}        commands to run once the scene is loaded (e.g. \":set n 3v0 1; :rec a.wav\")
+    --title {title}         window title
+    -h, --help              print this";
+
+#[derive(Resource, Default, Clone)]
+pub struct Cli {
+    pub headless: bool,
+    pub scene: Option<String>,
+    pub out_device: Option<(usize, usize)>,
+    pub in_device: Option<(usize, usize)>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+    pub osc_port: Option<u16>,
+    pub command: Option<String>,
+    pub title: Option<String>,
+}
+
+impl Cli {
+    pub fn parse() -> Self {
+        match Self::parse_from(std::env::args().skip(1)) {
+            Ok(cli) => cli,
+            Err(e) => {
+                if !e.is_empty() { eprintln!("{}", e); }
+                eprintln!("{}", USAGE);
+                std::process::exit(if e.is_empty() { 0 } else { 1 });
+            }
+        }
+    }
+
+    // an empty error means help was asked for
+    fn parse_from(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut cli = Cli::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Err(String::new()),
+                "--headless" => cli.headless = true,
+                "--out" => cli.out_device = Some((value(&arg, &mut args)?, value(&arg, &mut args)?)),
+                "--in" => cli.in_device = Some((value(&arg, &mut args)?, value(&arg, &mut args)?)),
+                "--sr" => cli.sample_rate = Some(value(&arg, &mut args)?),
+                "--buffer" => cli.buffer_size = Some(value(&arg, &mut args)?),
+                "--channels" => cli.channels = Some(value(&arg, &mut args)?),
+                "--osc" => cli.osc_port = Some(value(&arg, &mut args)?),
+                "--cmd" => cli.command = Some(value(&arg, &mut args)?),
+                "--title" => cli.title = Some(value(&arg, &mut args)?),
+                s if s.starts_with('-') => return Err(format!("unknown option: {}", s)),
+                _ => cli.scene = Some(arg),
+            }
+        }
+        Ok(cli)
+    }
+}
+
+fn value<T: std::str::FromStr>(arg: &str, args: &mut impl Iterator<Item = String>) -> Result<T, String> {
+    let s = args.next().ok_or(format!("missing value for {}", arg))?;
+    s.parse::<T>().map_err(|_| format!("bad value for {}: {}", arg, s))
+}
+
+pub fn apply_cli(
+    cli: Res<Cli>,
+    paste_chan: Res<PasteChannel>,
+    mut osc_receiver: ResMut<OscReceiver>,
+) {
+    if let Some(scene) = &cli.scene {
+        // spawned by paste_scene (after migrating it)
+        let path = scene_path(scene);
+        match std::fs::read_to_string(&path) {
+            Ok(string) => { let _ = paste_chan.0.0.try_send(string); }
+            Err(e) => error!("couldn't open {}: {}", path.display(), e),
+        }
+    }
+    if let Some(port) = cli.osc_port {
+        osc_receiver.init(port);
+    }
+}
+
+// run the startup commands once no scene is still loading
+pub fn run_cli_command(
+    cli: Res<Cli>,
+    scenes: Query<&Handle<DynamicScene>>,
+    paste_chan: Res<PasteChannel>,
+    asset_server: Res<AssetServer>,
+    mut remote: EventWriter<RemoteCommand>,
+    mut done: Local<bool>,
+) {
+    if *done { return; }
+    let Some(command) = &cli.command else {
+        *done = true;
+        return;
+    };
+    if !paste_chan.0.1.is_empty() { return; }
+    if scenes.iter().any(|h| !matches!(asset_server.load_state(h), LoadState::Failed(_))) { return; }
+    // runs like commands from osc (but anything goes)
+    remote.send(RemoteCommand(command.clone()));
+    *done = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, String> {
+        Cli::parse_from(args.split_ascii_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn options() {
+        let cli = parse("song.cute --headless --out 1 2 --sr 48000 --osc 1729 --title hi").unwrap();
+        assert_eq!(cli.scene.as_deref(), Some("song.cute"));
+        assert!(cli.headless);
+        assert_eq!(cli.out_device, Some((1, 2)));
+        assert_eq!(cli.in_device, None);
+        assert_eq!(cli.sample_rate, Some(48000));
+        assert_eq!(cli.osc_port, Some(1729));
+        assert_eq!(cli.title.as_deref(), Some("hi"));
+        let cli = Cli::parse_from(["--cmd".to_string(), ":set n 3v0 1; :rec a.wav".to_string()].into_iter()).unwrap();
+        assert_eq!(cli.command.as_deref(), Some(":set n 3v0 1; :rec a.wav"));
+    }
+
+    #[test]
+    fn command_runs_once() {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+            .add_event::<RemoteCommand>()
+            .insert_resource(PasteChannel(crossbeam_channel::bounded::<String>(1)))
+            .insert_resource(Cli { command: Some(":set n 3v0 1; :rec a.wav".to_string()), ..default() })
+            .add_systems(Update, run_cli_command);
+        // not while a scene is waiting to be spawned
+        let _ = app.world().resource::<PasteChannel>().0.0.try_send(String::new());
+        app.update();
+        assert!(app.world().resource::<Events<RemoteCommand>>().is_empty());
+        let _ = app.world().resource::<PasteChannel>().0.1.try_recv();
+        app.update();
+        app.update();
+        let events = app.world().resource::<Events<RemoteCommand>>();
+        let sent: Vec<&str> = events.get_reader().read(events).map(|c| c.0.as_str()).collect();
+        assert_eq!(sent, [":set n 3v0 1; :rec a.wav"]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("--help").err(), Some(String::new()));
+        assert_eq!(parse("--nope").err(), Some("unknown option: --nope".to_string()));
+        assert_eq!(parse("--sr").err(), Some("missing value for --sr".to_string()));
+        assert_eq!(parse("--out 1").err(), Some("missing value for --out".to_string()));
+        assert_eq!(parse("--osc big").err(), Some("bad value for --osc: big".to_string()));
+    }
+}
@@ -11,6 +11,10 @@ use bevy::{
 use crate::{
     components::*,
     functions::*,
+    midi::*,
+    osc::*,
+    oscquery::*,
+    scene::*,
 };
 
 use fundsp::audiounit::AudioUnit;
@@ -33,6 +37,8 @@ pub struct Access<'w, 's> {
     order_change: EventWriter<'w, OrderChange>,
     vertices_query: Query<'w, 's, &'static mut Vertices>,
     save_event: EventWriter<'w, SaveCommand>,
+    save_prefs_event: EventWriter<'w, SavePrefsCommand>,
+    history_event: EventWriter<'w, HistoryCommand>,
     copy_event: EventWriter<'w, CopyCommand>,
     delete_event: EventWriter<'w, DeleteCommand>,
     targets_query: Query<'w, 's, &'static mut Targets>,
@@ -56,6 +62,20 @@ pub struct Access<'w, 's> {
     op_num_query: Query<'w, 's, &'static mut OpNum>,
     clipboard: ResMut<'w, SystemClipboard>,
     paste_chan: Res<'w, PasteChannel>,
+    status_chan: Res<'w, StatusChannel>,
+    theme_chan: Res<'w, ThemeChannel>,
+    render_event: EventWriter<'w, RenderCommand>,
+    record_event: EventWriter<'w, RecordCommand>,
+    input_buffer: Res<'w, InputBufferRes>,
+    midi_in_event: EventWriter<'w, MidiInCommand>,
+    midi_out_event: EventWriter<'w, MidiOutCommand>,
+    osc_sender: ResMut<'w, OscSender>,
+    osc_receiver: ResMut<'w, OscReceiver>,
+    osc_query: ResMut<'w, OscQuery>,
+    exposed_query: Query<'w, 's, Entity, With<Exposed>>,
+    group_event: EventWriter<'w, GroupCommand>,
+    auto_order_event: EventWriter<'w, AutoOrderCommand>,
+    group_query: Query<'w, 's, Entity, With<Group>>,
 }
 
 pub fn command_parser(
@@ -67,13 +87,18 @@ pub fn command_parser(
     mut next_mode: ResMut<NextState<Mode>>,
     mode: Res<State<Mode>>,
     mut commands: Commands,
+    // only used on the web, where scenes are loaded as assets
+    #[cfg_attr(not(target_arch = "wasm32"), allow(unused_variables))]
     asset_server: Res<AssetServer>,
     info_text_query: Query<(Entity, &InfoText)>,
     mut ids_shown: Local<bool>,
     holes_query: Query<&Holes>,
+    mut remote_commands: EventReader<RemoteCommand>,
 ) {
+    let remote: Vec<String> = remote_commands.read().map(|c| c.0.clone()).collect();
     let clt = &mut command_line_text.single_mut();
     if key_event.is_empty()
+    && remote.is_empty()
     && !clt.is_changed()
     && !keyboard_input.just_released(KeyCode::KeyT) { return; }
 
@@ -108,9 +133,7 @@ pub fn command_parser(
         if text.starts_with("-- CONNECT")
         && keyboard_input.just_pressed(KeyCode::KeyC) {
             *text = "-- LT --> ".to_string();
-            return;
-        }
-        if text.starts_with("-- LT --> ") {
+        } else if text.starts_with("-- LT --> ") {
             for key in key_event.read() {
                 if key.state.is_pressed() {
                     if let Key::Character(c) = &key.logical_key {
@@ -124,25 +147,25 @@ pub fn command_parser(
                     }
                 }
             }
-            return;
-        }
-        if !keyboard_input.pressed(KeyCode::KeyT) {
-            *text = format!("-- CONNECT -- ({} {})",
-                lt_to_string(access.default_lt.0.0),
-                lt_to_string(access.default_lt.0.1));
-        }
-        // exit to edit
-        if keyboard_input.any_just_pressed([KeyCode::Escape, KeyCode::KeyE]) {
-            text.clear();
-            next_mode.set(Mode::Edit);
-            key_event.clear(); // consume the 'e' when exiting to edit
-        }
-        // target
-        if keyboard_input.just_pressed(KeyCode::KeyT) { *text = "-- TARGET --".to_string(); }
-        // switch to draw mode
-        if keyboard_input.just_pressed(KeyCode::KeyD) {
-            *text = "-- DRAW --".to_string();
-            next_mode.set(Mode::Draw);
+        } else {
+            if !keyboard_input.pressed(KeyCode::KeyT) {
+                *text = format!("-- CONNECT -- ({} {})",
+                    lt_to_string(access.default_lt.0.0),
+                    lt_to_string(access.default_lt.0.1));
+            }
+            // exit to edit
+            if keyboard_input.any_just_pressed([KeyCode::Escape, KeyCode::KeyE]) {
+                text.clear();
+                next_mode.set(Mode::Edit);
+                key_event.clear(); // consume the 'e' when exiting to edit
+            }
+            // target
+            if keyboard_input.just_pressed(KeyCode::KeyT) { *text = "-- TARGET --".to_string(); }
+            // switch to draw mode
+            if keyboard_input.just_pressed(KeyCode::KeyD) {
+                *text = "-- DRAW --".to_string();
+                next_mode.set(Mode::Draw);
+            }
         }
     } else if *mode.get() == Mode::Edit {
         for key in key_event.read() {
@@ -167,237 +190,324 @@ pub fn command_parser(
                 }
             }
         }
-        if text.ends_with('\t') {
-            // commands starting with :
-            let lines = text.as_str().split(';');
-            for line in lines {
-                // (entity, lt) if there's a given entity
-                let mut lt_to_open = (None, None);
-                let mut command = line.split_ascii_whitespace();
-                let c0 = command.next();
-                match c0 {
-                    // open scene file
-                    Some(":e") => {
-                        if let Some(s) = command.next() {
-                            commands.spawn(DynamicSceneBundle {
-                                scene: asset_server.load(s.to_string()),
-                                ..default()
-                            });
-                        }
+    }
+    // commands from osc run in any mode, and leave what's being typed alone
+    let typed = *mode.get() == Mode::Edit && text.ends_with('\t');
+    if typed || !remote.is_empty() {
+        // commands starting with :
+        let typed_text = if typed { text.clone() } else { String::new() };
+        let lines = remote.iter().chain(typed.then_some(&typed_text)).flat_map(|t| t.split(';'));
+        for line in lines {
+            // (entity, lt) if there's a given entity
+            let mut lt_to_open = (None, None);
+            let mut command = line.split_ascii_whitespace();
+            let c0 = command.next();
+            match c0 {
+                // open scene file
+                Some(":e") => {
+                    if let Some(s) = command.next() {
+                        // read it ourselves so older scenes can be migrated
+                        #[cfg(not(target_arch = "wasm32"))]
+                        open_scene(
+                            scene_path(s),
+                            access.paste_chan.0.0.clone(),
+                            access.status_chan.0.0.clone(),
+                        );
+                        #[cfg(target_arch = "wasm32")]
+                        commands.spawn(DynamicSceneBundle {
+                            scene: asset_server.load(s.to_string()),
+                            ..default()
+                        });
                     }
-                    // save scene file
-                    Some(":w") => {
-                        if let Some(s) = command.next() {
-                            access.save_event.send(SaveCommand(s.to_string()));
-                        }
+                }
+                // use the colors of a scene file
+                #[cfg(not(target_arch = "wasm32"))]
+                Some(":theme") => {
+                    if let Some(s) = command.next() {
+                        open_scene(
+                            scene_path(s),
+                            access.theme_chan.0.0.clone(),
+                            access.status_chan.0.0.clone(),
+                        );
                     }
-                    Some(":q") => {
-                        access.exit_event.send_default();
+                }
+                // save scene file (:w! to overwrite)
+                Some(":w") | Some(":w!") => {
+                    if let Some(s) = command.next() {
+                        access.save_event.send(SaveCommand(s.to_string(), c0 == Some(":w!")));
                     }
-                    Some(":od") | Some(":id") => {
-                        let h = command.next();
-                        let d = command.next();
-                        let mut sr = None;
-                        let mut b = None;
-                        if let (Some(h), Some(d)) = (h, d) {
-                            let h = h.parse::<usize>();
-                            let d = d.parse::<usize>();
-                            if let (Ok(h), Ok(d)) = (h, d) {
-                                let samplerate = command.next();
-                                let block = command.next();
-                                if let Some(samplerate) = samplerate {
-                                    if let Ok(samplerate) = samplerate.parse::<u32>() {
-                                        sr = Some(samplerate);
-                                    }
-                                }
-                                if let Some(block) = block {
-                                    if let Ok(block) = block.parse::<u32>() {
-                                        b = Some(block);
-                                    }
+                }
+                Some(":saveprefs") => {
+                    access.save_prefs_event.send_default();
+                }
+                Some(":q") => {
+                    access.exit_event.send_default();
+                }
+                Some(":od") | Some(":id") => {
+                    let h = command.next();
+                    let d = command.next();
+                    let mut sr = None;
+                    let mut b = None;
+                    if let (Some(h), Some(d)) = (h, d) {
+                        let h = h.parse::<usize>();
+                        let d = d.parse::<usize>();
+                        if let (Ok(h), Ok(d)) = (h, d) {
+                            let samplerate = command.next();
+                            let block = command.next();
+                            if let Some(samplerate) = samplerate {
+                                if let Ok(samplerate) = samplerate.parse::<u32>() {
+                                    sr = Some(samplerate);
                                 }
-                                if c0 == Some(":od") {
-                                    access.out_device_event.send(OutDeviceCommand(h, d, sr, b));
-                                } else {
-                                    access.in_device_event.send(InDeviceCommand(h, d, sr, b));
+                            }
+                            if let Some(block) = block {
+                                if let Ok(block) = block.parse::<u32>() {
+                                    b = Some(block);
                                 }
                             }
-                        }
-                    }
-                    Some(":nl") => {
-                        if let Some(s) = command.next() {
-                            if let Ok(n) = s.parse::<usize>() {
-                                access.node_limit.0 = n;
+                            let ch = command.next().and_then(|s| s.parse::<u16>().ok());
+                            if c0 == Some(":od") {
+                                access.out_device_event.send(OutDeviceCommand(h, d, sr, b, ch));
+                            } else {
+                                access.in_device_event.send(InDeviceCommand(h, d, sr, b, ch));
                             }
                         }
                     }
-                    // white hole / black hole link type
-                    // TODO(amy): set-both-ends version
-                    Some(":lt") | Some("lt") => {
-                        if let Some(s) = command.next() {
-                            if let Some(e) = str_to_id(s) {
-                                if let Ok(mut wh) = access.white_hole_query.get_mut(e) {
-                                    if let Some(s) = command.next() {
-                                        wh.link_types.1 = str_to_lt(s);
-                                        wh.open = true;
-                                        let parent = access.black_hole_query.get(wh.bh).unwrap().wh_parent;
-                                        access.gained_wh_query.get_mut(parent).unwrap().0 = true;
-                                    }
-                                } else if let Ok(bh) = access.black_hole_query.get(e) {
-                                    let wh = &mut access.white_hole_query.get_mut(bh.wh).unwrap();
-                                    if let Some(s) = command.next() {
-                                        wh.link_types.0 = str_to_lt(s);
-                                        wh.open = true;
-                                        let parent = access.white_hole_query.get(bh.wh).unwrap().bh_parent;
-                                        access.gained_wh_query.get_mut(parent).unwrap().0 = true;
-                                    }
-                                }
-                            } else {
-                                for id in access.selected_query.iter() {
-                                    if let Ok(mut wh) = access.white_hole_query.get_mut(id) {
-                                        wh.link_types.1 = str_to_lt(s);
-                                        wh.open = true;
-                                        let parent = access.black_hole_query.get(wh.bh).unwrap().wh_parent;
-                                        access.gained_wh_query.get_mut(parent).unwrap().0 = true;
-                                    } else if let Ok(bh) = access.black_hole_query.get(id) {
-                                        let wh = &mut access.white_hole_query.get_mut(bh.wh).unwrap();
-                                        wh.link_types.0 = str_to_lt(s);
-                                        wh.open = true;
-                                        let parent = access.white_hole_query.get(bh.wh).unwrap().bh_parent;
-                                        access.gained_wh_query.get_mut(parent).unwrap().0 = true;
-                                    }
-                                }
-                            }
+                }
+                Some(":render") => {
+                    let file = command.next();
+                    let dur = command.next().and_then(|s| s.parse::<f32>().ok());
+                    if let (Some(file), Some(dur)) = (file, dur) {
+                        let sr = command.next().and_then(|s| s.parse::<u32>().ok());
+                        let bits = command.next().and_then(|s| s.parse::<u32>().ok());
+                        access.render_event.send(RenderCommand(file.to_string(), dur, sr, bits));
+                    }
+                }
+                Some(":rec") => {
+                    let file = command.next().map(|s| s.to_string());
+                    access.record_event.send(RecordCommand(file));
+                }
+                Some(":ir") => {
+                    if let Some(s) = command.next() {
+                        let on = s != "0";
+                        access.input_buffer.0.adaptive.store(on, std::sync::atomic::Ordering::Relaxed);
+                    }
+                }
+                Some(":mi") => {
+                    if let Some(s) = command.next() {
+                        if let Ok(i) = s.parse::<usize>() {
+                            access.midi_in_event.send(MidiInCommand(Some(i)));
+                        } else if s == "v" {
+                            access.midi_in_event.send(MidiInCommand(None));
                         }
                     }
-                    Some(":dv") | Some("dv") => {
-                        if let Some(s) = command.next() {
-                            if let Ok(n) = s.parse::<usize>() {
-                                access.default_verts.0 = n.clamp(3,64);
-                            }
+                }
+                Some(":mo") => {
+                    if let Some(s) = command.next() {
+                        if let Ok(i) = s.parse::<usize>() {
+                            access.midi_out_event.send(MidiOutCommand(Some(i)));
+                        } else if s == "v" {
+                            access.midi_out_event.send(MidiOutCommand(None));
                         }
                     }
-                    Some(":dc") | Some("dc") => {
-                        let mut h = 270.;
-                        let mut s = 1.;
-                        let mut l = 0.5;
-                        let mut a = 1.;
-                        if let Some(n) = command.next() {
-                            if let Ok(n) = n.parse::<f32>() { h = n; }
+                }
+                // osc endpoints
+                Some(":oscr") => {
+                    if let Some(name) = command.next() {
+                        if let Some(port) = command.next().and_then(|s| s.parse::<u16>().ok()) {
+                            let tcp = command.next() == Some("tcp");
+                            access.osc_receiver.listen(name, port, tcp);
+                        } else {
+                            access.osc_receiver.close(name);
                         }
-                        if let Some(n) = command.next() {
-                            if let Ok(n) = n.parse::<f32>() { s = n; }
+                    }
+                }
+                Some(":oscs") => {
+                    if let Some(name) = command.next() {
+                        let host = command.next();
+                        let port = command.next().and_then(|s| s.parse::<u16>().ok());
+                        if let (Some(host), Some(port)) = (host, port) {
+                            let tcp = command.next() == Some("tcp");
+                            access.osc_sender.add_endpoint(name, host, port, tcp);
+                        } else {
+                            access.osc_sender.remove_endpoint(name);
                         }
-                        if let Some(n) = command.next() {
-                            if let Ok(n) = n.parse::<f32>() { l = n; }
+                    }
+                }
+                Some(":oq") => {
+                    if let Some(port) = command.next().and_then(|s| s.parse::<u16>().ok()) {
+                        access.osc_query.start(port);
+                    } else {
+                        access.osc_query.stop();
+                    }
+                }
+                // toggle exposing the selected circles through oscquery
+                Some(":ex") => {
+                    for e in access.selected_query.iter() {
+                        if access.exposed_query.contains(e) {
+                            commands.entity(e).remove::<Exposed>();
+                        } else {
+                            commands.entity(e).insert(Exposed);
                         }
-                        if let Some(n) = command.next() {
-                            if let Ok(n) = n.parse::<f32>() { a = n; }
+                    }
+                }
+                Some(":autoorder") => {
+                    access.auto_order_event.send_default();
+                }
+                Some(":group") => {
+                    access.group_event.send(GroupCommand(true));
+                }
+                Some(":ungroup") => {
+                    access.group_event.send(GroupCommand(false));
+                }
+                Some(":nl") => {
+                    if let Some(s) = command.next() {
+                        if let Ok(n) = s.parse::<usize>() {
+                            access.node_limit.0 = n;
                         }
-                        access.default_color.0 = Hsla::new(h,s,l,a);
                     }
-                    // toggle open a white hole (by id)
-                    Some(":ht") | Some("ht") => {
-                        if let Some(s) = command.next() {
-                            if let Some(e) = str_to_id(s) {
-                                if let Ok(mut wh) = access.white_hole_query.get_mut(e) {
-                                    wh.open = !wh.open;
+                }
+                // white hole / black hole link type
+                // TODO(amy): set-both-ends version
+                Some(":lt") | Some("lt") => {
+                    if let Some(s) = command.next() {
+                        if let Some(e) = str_to_id(s) {
+                            if let Ok(mut wh) = access.white_hole_query.get_mut(e) {
+                                if let Some(s) = command.next() {
+                                    wh.link_types.1 = str_to_lt(s);
+                                    wh.open = true;
+                                    let parent = access.black_hole_query.get(wh.bh).unwrap().wh_parent;
+                                    access.gained_wh_query.get_mut(parent).unwrap().0 = true;
+                                }
+                            } else if let Ok(bh) = access.black_hole_query.get(e) {
+                                let wh = &mut access.white_hole_query.get_mut(bh.wh).unwrap();
+                                if let Some(s) = command.next() {
+                                    wh.link_types.0 = str_to_lt(s);
+                                    wh.open = true;
+                                    let parent = access.white_hole_query.get(bh.wh).unwrap().bh_parent;
+                                    access.gained_wh_query.get_mut(parent).unwrap().0 = true;
+                                }
+                            }
+                        } else {
+                            for id in access.selected_query.iter() {
+                                if let Ok(mut wh) = access.white_hole_query.get_mut(id) {
+                                    wh.link_types.1 = str_to_lt(s);
+                                    wh.open = true;
+                                    let parent = access.black_hole_query.get(wh.bh).unwrap().wh_parent;
+                                    access.gained_wh_query.get_mut(parent).unwrap().0 = true;
+                                } else if let Ok(bh) = access.black_hole_query.get(id) {
+                                    let wh = &mut access.white_hole_query.get_mut(bh.wh).unwrap();
+                                    wh.link_types.0 = str_to_lt(s);
+                                    wh.open = true;
+                                    let parent = access.white_hole_query.get(bh.wh).unwrap().bh_parent;
+                                    access.gained_wh_query.get_mut(parent).unwrap().0 = true;
                                 }
                             }
                         }
                     }
-                    Some(":push") | Some("push") => {
-                        if let Some(a1) = command.next() {
-                            if let Some(a2) = command.next() {
-                                if let Some(e) = str_to_id(a1) {
-                                    if let Some(t) = str_to_id(a2) {
-                                        if let Ok(mut targets) = access.targets_query.get_mut(e) {
-                                            targets.0.push(t);
-                                        }
-                                    } else if let Ok(n) = parse_with_constants(a2) {
-                                        if let Ok(mut arr) = access.arr_query.get_mut(e) {
-                                            arr.0.push(n);
-                                        }
+                }
+                Some(":dv") | Some("dv") => {
+                    if let Some(s) = command.next() {
+                        if let Ok(n) = s.parse::<usize>() {
+                            access.default_verts.0 = n.clamp(3,64);
+                        }
+                    }
+                }
+                Some(":dc") | Some("dc") => {
+                    let mut h = 270.;
+                    let mut s = 1.;
+                    let mut l = 0.5;
+                    let mut a = 1.;
+                    if let Some(n) = command.next() {
+                        if let Ok(n) = n.parse::<f32>() { h = n; }
+                    }
+                    if let Some(n) = command.next() {
+                        if let Ok(n) = n.parse::<f32>() { s = n; }
+                    }
+                    if let Some(n) = command.next() {
+                        if let Ok(n) = n.parse::<f32>() { l = n; }
+                    }
+                    if let Some(n) = command.next() {
+                        if let Ok(n) = n.parse::<f32>() { a = n; }
+                    }
+                    access.default_color.0 = Hsla::new(h,s,l,a);
+                }
+                // toggle open a white hole (by id)
+                Some(":ht") | Some("ht") => {
+                    if let Some(s) = command.next() {
+                        if let Some(e) = str_to_id(s) {
+                            if let Ok(mut wh) = access.white_hole_query.get_mut(e) {
+                                wh.open = !wh.open;
+                            }
+                        }
+                    }
+                }
+                Some(":push") | Some("push") => {
+                    if let Some(a1) = command.next() {
+                        if let Some(a2) = command.next() {
+                            if let Some(e) = str_to_id(a1) {
+                                if let Some(t) = str_to_id(a2) {
+                                    if let Ok(mut targets) = access.targets_query.get_mut(e) {
+                                        targets.0.push(t);
+                                    }
+                                } else if let Ok(n) = parse_with_constants(a2) {
+                                    if let Ok(mut arr) = access.arr_query.get_mut(e) {
+                                        arr.0.push(n);
                                     }
                                 }
-                            } else {
-                                for id in access.selected_query.iter() {
-                                    if let Some(t) = str_to_id(a1) {
-                                        if let Ok(mut targets) = access.targets_query.get_mut(id) {
-                                            targets.0.push(t);
-                                        }
-                                    } else if let Ok(n) = parse_with_constants(a1) {
-                                        if let Ok(mut arr) = access.arr_query.get_mut(id) {
-                                            arr.0.push(n);
-                                        }
+                            }
+                        } else {
+                            for id in access.selected_query.iter() {
+                                if let Some(t) = str_to_id(a1) {
+                                    if let Ok(mut targets) = access.targets_query.get_mut(id) {
+                                        targets.0.push(t);
+                                    }
+                                } else if let Ok(n) = parse_with_constants(a1) {
+                                    if let Ok(mut arr) = access.arr_query.get_mut(id) {
+                                        arr.0.push(n);
                                     }
                                 }
                             }
                         }
                     }
-                    Some(":set") | Some("set") | Some(":delta") | Some("delta") => {
-                        let c1 = command.next();
-                        match c1 {
-                            Some("n") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut num) = access.num_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        num.0 = n;
-                                                    } else {
-                                                        num.0 += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-1));
-                                                }
-                                            }
-                                        }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut num) = access.num_query.get_mut(id) {
+                }
+                Some(":set") | Some("set") | Some(":delta") | Some("delta") => {
+                    let c1 = command.next();
+                    match c1 {
+                        Some("n") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut num) = access.num_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     num.0 = n;
                                                 } else {
                                                     num.0 += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-1));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-1));
                                     }
-                                }
-                            }
-                            Some("r") | Some("rx") | Some("ry") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut trans) = access.trans_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        if c1 == Some("r") {
-                                                            trans.scale.x = n.max(0.);
-                                                            trans.scale.y = n.max(0.);
-                                                        } else if c1 == Some("rx") {
-                                                            trans.scale.x = n.max(0.);
-                                                        } else {
-                                                            trans.scale.y = n.max(0.);
-                                                        }
-                                                    } else {
-                                                        if c1 == Some("r") {
-                                                            trans.scale.x = (trans.scale.x+n).max(0.);
-                                                            trans.scale.y = (trans.scale.y+n).max(0.);
-                                                        } else if c1 == Some("rx") {
-                                                            trans.scale.x = (trans.scale.x+n).max(0.);
-                                                        } else {
-                                                            trans.scale.y = (trans.scale.y+n).max(0.);
-                                                        }
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-2));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut num) = access.num_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                num.0 = n;
+                                            } else {
+                                                num.0 += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut trans) = access.trans_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-1));
+                                }
+                            }
+                        }
+                        Some("r") | Some("rx") | Some("ry") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut trans) = access.trans_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     if c1 == Some("r") {
                                                         trans.scale.x = n.max(0.);
@@ -417,323 +527,337 @@ pub fn command_parser(
                                                         trans.scale.y = (trans.scale.y+n).max(0.);
                                                     }
                                                 }
+                                                lt_to_open = (Some(e), Some(-2));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-2));
                                     }
-                                }
-                            }
-                            Some("x") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut t) = access.trans_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        t.translation.x = n;
-                                                    } else {
-                                                        t.translation.x += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-3));
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut trans) = access.trans_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                if c1 == Some("r") {
+                                                    trans.scale.x = n.max(0.);
+                                                    trans.scale.y = n.max(0.);
+                                                } else if c1 == Some("rx") {
+                                                    trans.scale.x = n.max(0.);
+                                                } else {
+                                                    trans.scale.y = n.max(0.);
+                                                }
+                                            } else {
+                                                if c1 == Some("r") {
+                                                    trans.scale.x = (trans.scale.x+n).max(0.);
+                                                    trans.scale.y = (trans.scale.y+n).max(0.);
+                                                } else if c1 == Some("rx") {
+                                                    trans.scale.x = (trans.scale.x+n).max(0.);
+                                                } else {
+                                                    trans.scale.y = (trans.scale.y+n).max(0.);
                                                 }
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-2));
+                                }
+                            }
+                        }
+                        Some("x") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut t) = access.trans_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     t.translation.x = n;
                                                 } else {
                                                     t.translation.x += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-3));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-3));
                                     }
-                                }
-                            }
-                            Some("y") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut t) = access.trans_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        t.translation.y = n;
-                                                    } else {
-                                                        t.translation.y += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-4));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                t.translation.x = n;
+                                            } else {
+                                                t.translation.x += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-3));
+                                }
+                            }
+                        }
+                        Some("y") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut t) = access.trans_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     t.translation.y = n;
                                                 } else {
                                                     t.translation.y += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-4));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-4));
                                     }
-                                }
-                            }
-                            Some("z") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut t) = access.trans_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        t.translation.z = n;
-                                                    } else {
-                                                        t.translation.z += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-5));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                t.translation.y = n;
+                                            } else {
+                                                t.translation.y += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-4));
+                                }
+                            }
+                        }
+                        Some("z") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut t) = access.trans_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     t.translation.z = n;
                                                 } else {
                                                     t.translation.z += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-5));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-5));
                                     }
-                                }
-                            }
-                            Some("h") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut color) = access.col_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        color.0.hue = n;
-                                                    } else {
-                                                        color.0.hue += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-6));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                t.translation.z = n;
+                                            } else {
+                                                t.translation.z += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut color) = access.col_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-5));
+                                }
+                            }
+                        }
+                        Some("h") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut color) = access.col_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     color.0.hue = n;
                                                 } else {
                                                     color.0.hue += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-6));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-6));
                                     }
-                                }
-                            }
-                            Some("s") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut color) = access.col_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        color.0.saturation = n;
-                                                    } else {
-                                                        color.0.saturation += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-7));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut color) = access.col_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                color.0.hue = n;
+                                            } else {
+                                                color.0.hue += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut color) = access.col_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-6));
+                                }
+                            }
+                        }
+                        Some("s") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut color) = access.col_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     color.0.saturation = n;
                                                 } else {
                                                     color.0.saturation += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-7));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-7));
                                     }
-                                }
-                            }
-                            Some("l") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut color) = access.col_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        color.0.lightness = n;
-                                                    } else {
-                                                        color.0.lightness += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-8));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut color) = access.col_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                color.0.saturation = n;
+                                            } else {
+                                                color.0.saturation += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut color) = access.col_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-7));
+                                }
+                            }
+                        }
+                        Some("l") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut color) = access.col_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     color.0.lightness = n;
                                                 } else {
                                                     color.0.lightness += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-8));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-8));
                                     }
-                                }
-                            }
-                            Some("a") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut color) = access.col_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        color.0.alpha = n;
-                                                    } else {
-                                                        color.0.alpha += n;
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-9));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut color) = access.col_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                color.0.lightness = n;
+                                            } else {
+                                                color.0.lightness += n;
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut color) = access.col_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-8));
+                                }
+                            }
+                        }
+                        Some("a") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut color) = access.col_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     color.0.alpha = n;
                                                 } else {
                                                     color.0.alpha += n;
                                                 }
+                                                lt_to_open = (Some(e), Some(-9));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-9));
                                     }
-                                }
-                            }
-                            Some("v") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut vertices) = access.vertices_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = n.parse::<usize>() {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        vertices.0 = n.max(3);
-                                                    } else {
-                                                        vertices.0 = (vertices.0+n).max(3);
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-11));
-                                                }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut color) = access.col_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                color.0.alpha = n;
+                                            } else {
+                                                color.0.alpha += n;
                                             }
                                         }
-                                    } else if let Ok(n) = s.parse::<usize>() {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut vertices) = access.vertices_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-9));
+                                }
+                            }
+                        }
+                        Some("v") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut vertices) = access.vertices_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = n.parse::<usize>() {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     vertices.0 = n.max(3);
                                                 } else {
                                                     vertices.0 = (vertices.0+n).max(3);
                                                 }
+                                                lt_to_open = (Some(e), Some(-11));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-11));
                                     }
-                                }
-                            }
-                            Some("o") | Some("rot") | Some("rotation") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut t) = access.trans_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        t.rotation = Quat::from_rotation_z(n);
-                                                    } else {
-                                                        let rot = t.rotation.to_euler(EulerRot::XYZ).2;
-                                                        t.rotation = Quat::from_rotation_z(rot + n);
-                                                    }
-                                                    lt_to_open = (Some(e), Some(-12));
-                                                }
+                                } else if let Ok(n) = s.parse::<usize>() {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut vertices) = access.vertices_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                vertices.0 = n.max(3);
+                                            } else {
+                                                vertices.0 = (vertices.0+n).max(3);
                                             }
                                         }
-                                    } else if let Ok(n) = parse_with_constants(s) {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                    }
+                                    lt_to_open = (None, Some(-11));
+                                }
+                            }
+                        }
+                        Some("o") | Some("rot") | Some("rotation") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut t) = access.trans_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = parse_with_constants(n) {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     t.rotation = Quat::from_rotation_z(n);
                                                 } else {
                                                     let rot = t.rotation.to_euler(EulerRot::XYZ).2;
                                                     t.rotation = Quat::from_rotation_z(rot + n);
                                                 }
+                                                lt_to_open = (Some(e), Some(-12));
                                             }
                                         }
-                                        lt_to_open = (None, Some(-12));
                                     }
+                                } else if let Ok(n) = parse_with_constants(s) {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut t) = access.trans_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                t.rotation = Quat::from_rotation_z(n);
+                                            } else {
+                                                let rot = t.rotation.to_euler(EulerRot::XYZ).2;
+                                                t.rotation = Quat::from_rotation_z(rot + n);
+                                            }
+                                        }
+                                    }
+                                    lt_to_open = (None, Some(-12));
                                 }
                             }
-                            Some("op") => {
-                                if let Some(s) = command.next() {
-                                    let op_str = line.trim(); // we have a \t at the end
-                                    let op_str = if op_str.starts_with(':') {
-                                        op_str.trim_start_matches(":set op ")
-                                    } else {
-                                        op_str.trim_start_matches("set op ")
-                                    };
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut op) = access.op_query.get_mut(e) {
-                                            let op_str = op_str.trim_start_matches(s).trim_start();
+                        }
+                        Some("op") => {
+                            if let Some(s) = command.next() {
+                                let op_str = line.trim(); // we have a \t at the end
+                                let op_str = if op_str.starts_with(':') {
+                                    op_str.trim_start_matches(":set op ")
+                                } else {
+                                    op_str.trim_start_matches("set op ")
+                                };
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut op) = access.op_query.get_mut(e) {
+                                        let op_str = op_str.trim_start_matches(s).trim_start();
+                                        op.0 = op_str.into();
+                                        access.op_changed_query.get_mut(e).unwrap().0 = true;
+                                        access.net_query.get_mut(e).unwrap().0 = str_to_net(op_str);
+                                        access.op_num_query.get_mut(e).unwrap().0 = str_to_op_num(op_str);
+                                        lt_to_open = (Some(e), Some(0));
+                                    }
+                                } else {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut op) = access.op_query.get_mut(id) {
                                             op.0 = op_str.into();
-                                            access.op_changed_query.get_mut(e).unwrap().0 = true;
-                                            access.net_query.get_mut(e).unwrap().0 = str_to_net(op_str);
-                                            access.op_num_query.get_mut(e).unwrap().0 = str_to_op_num(op_str);
-                                            lt_to_open = (Some(e), Some(0));
+                                            access.op_changed_query.get_mut(id).unwrap().0 = true;
+                                            access.net_query.get_mut(id).unwrap().0 = str_to_net(op_str);
+                                            access.op_num_query.get_mut(id).unwrap().0 = str_to_op_num(op_str);
                                         }
-                                    } else {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut op) = access.op_query.get_mut(id) {
-                                                op.0 = op_str.into();
-                                                access.op_changed_query.get_mut(id).unwrap().0 = true;
-                                                access.net_query.get_mut(id).unwrap().0 = str_to_net(op_str);
-                                                access.op_num_query.get_mut(id).unwrap().0 = str_to_op_num(op_str);
-                                            }
-                                        }
-                                        lt_to_open = (None, Some(0));
                                     }
+                                    lt_to_open = (None, Some(0));
                                 }
                             }
-                            Some("ord") | Some("order") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut order) = access.order_query.get_mut(e) {
-                                            if let Some(n) = command.next() {
-                                                if let Ok(n) = n.parse::<f32>() {
-                                                    if c0 == Some(":set") || c0 == Some("set") {
-                                                        order.0 = n as usize;
-                                                    } else {
-                                                        order.0 = (order.0 as f32 + n) as usize;
-                                                    }
-                                                    access.order_change.send_default();
-                                                }
-                                            }
-                                        }
-                                    } else if let Ok(n) = s.parse::<f32>() {
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut order) = access.order_query.get_mut(id) {
+                        }
+                        Some("ord") | Some("order") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut order) = access.order_query.get_mut(e) {
+                                        if let Some(n) = command.next() {
+                                            if let Ok(n) = n.parse::<f32>() {
                                                 if c0 == Some(":set") || c0 == Some("set") {
                                                     order.0 = n as usize;
                                                 } else {
@@ -743,98 +867,97 @@ pub fn command_parser(
                                             }
                                         }
                                     }
-                                }
-                            }
-                            Some("arr") | Some("array") => {
-                                if let Some(s) = command.next() {
-                                    if let Some(e) = str_to_id(s) {
-                                        if let Ok(mut arr) = access.arr_query.get_mut(e) {
-                                            arr.0.clear();
-                                            for n in command {
-                                                if let Ok(n) = parse_with_constants(n) {
-                                                    arr.0.push(n);
-                                                }
+                                } else if let Ok(n) = s.parse::<f32>() {
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut order) = access.order_query.get_mut(id) {
+                                            if c0 == Some(":set") || c0 == Some("set") {
+                                                order.0 = n as usize;
+                                            } else {
+                                                order.0 = (order.0 as f32 + n) as usize;
                                             }
-                                            lt_to_open = (Some(e), Some(-13));
+                                            access.order_change.send_default();
                                         }
-                                    } else {
-                                        let mut tmp = Vec::new();
-                                        if let Ok(n) = parse_with_constants(s) { tmp.push(n); }
+                                    }
+                                }
+                            }
+                        }
+                        Some("arr") | Some("array") => {
+                            if let Some(s) = command.next() {
+                                if let Some(e) = str_to_id(s) {
+                                    if let Ok(mut arr) = access.arr_query.get_mut(e) {
+                                        arr.0.clear();
                                         for n in command {
                                             if let Ok(n) = parse_with_constants(n) {
-                                                tmp.push(n);
-                                            }
-                                        }
-                                        for id in access.selected_query.iter() {
-                                            if let Ok(mut arr) = access.arr_query.get_mut(id) {
-                                                arr.0 = tmp.clone();
+                                                arr.0.push(n);
                                             }
                                         }
-                                        lt_to_open = (None, Some(-13));
-                                    }
-                                }
-                            }
-                            Some("tar") | Some("targets") => {
-                                let mut tmp = Vec::new();
-                                for e in command {
-                                    if let Some(e) = str_to_id(e) {
-                                        tmp.push(e);
+                                        lt_to_open = (Some(e), Some(-13));
                                     }
-                                }
-                                // set the rest (cdr) as the targets of first (car)
-                                if access.selected_query.is_empty() {
-                                    if !tmp.is_empty() {
-                                        let controller = tmp.remove(0);
-                                        if let Ok(mut c) = access.targets_query.get_mut(controller) {
-                                            c.0 = tmp;
-                                            lt_to_open = (Some(controller), Some(-14));
+                                } else {
+                                    let mut tmp = Vec::new();
+                                    if let Ok(n) = parse_with_constants(s) { tmp.push(n); }
+                                    for n in command {
+                                        if let Ok(n) = parse_with_constants(n) {
+                                            tmp.push(n);
                                         }
                                     }
-                                } else {
-                                    // all selected circles get the list of entities as targets
-                                    for e in access.selected_query.iter() {
-                                        if let Ok(mut c) = access.targets_query.get_mut(e) {
-                                            c.0 = tmp.clone();
+                                    for id in access.selected_query.iter() {
+                                        if let Ok(mut arr) = access.arr_query.get_mut(id) {
+                                            arr.0 = tmp.clone();
                                         }
                                     }
-                                    lt_to_open = (None, Some(-14));
+                                    lt_to_open = (None, Some(-13));
                                 }
                             }
-                            _ => {}
                         }
-                    }
-                    // target selected
-                    Some(":tsel") => {
-                        if let Some(s) = command.next() {
-                            if let Some(e) = str_to_id(s) {
-                                if let Ok(mut targets) = access.targets_query.get_mut(e) {
-                                    targets.0.clear();
-                                    for selected in access.selected_query.iter() {
-                                        targets.0.push(selected);
+                        Some("tar") | Some("targets") => {
+                            let mut tmp = Vec::new();
+                            for e in command {
+                                if let Some(e) = str_to_id(e) {
+                                    tmp.push(e);
+                                }
+                            }
+                            // set the rest (cdr) as the targets of first (car)
+                            if access.selected_query.is_empty() {
+                                if !tmp.is_empty() {
+                                    let controller = tmp.remove(0);
+                                    if let Ok(mut c) = access.targets_query.get_mut(controller) {
+                                        c.0 = tmp;
+                                        lt_to_open = (Some(controller), Some(-14));
                                     }
-                                    lt_to_open = (Some(e), Some(-14));
                                 }
+                            } else {
+                                // all selected circles get the list of entities as targets
+                                for e in access.selected_query.iter() {
+                                    if let Ok(mut c) = access.targets_query.get_mut(e) {
+                                        c.0 = tmp.clone();
+                                    }
+                                }
+                                lt_to_open = (None, Some(-14));
                             }
                         }
+                        _ => {}
                     }
-                    _ => {}
                 }
-                // open all white holes reading whatever changed
-                if let (None, Some(lt)) = lt_to_open {
-                    for id in access.selected_query.iter() {
-                        if let Ok(holes) = holes_query.get(id) {
-                            for hole in &holes.0 {
-                                if let Ok(bh) = access.black_hole_query.get(*hole) {
-                                    if let Ok(wh) = access.white_hole_query.get_mut(bh.wh) {
-                                        if wh.link_types.0 == lt {
-                                            access.white_hole_query.get_mut(bh.wh).unwrap().open = true;
-                                        }
-                                    }
+                // target selected
+                Some(":tsel") => {
+                    if let Some(s) = command.next() {
+                        if let Some(e) = str_to_id(s) {
+                            if let Ok(mut targets) = access.targets_query.get_mut(e) {
+                                targets.0.clear();
+                                for selected in access.selected_query.iter() {
+                                    targets.0.push(selected);
                                 }
+                                lt_to_open = (Some(e), Some(-14));
                             }
                         }
                     }
-                } else if let (Some(id), Some(lt)) = lt_to_open {
+                }
+                _ => {}
+            }
+            // open all white holes reading whatever changed
+            if let (None, Some(lt)) = lt_to_open {
+                for id in access.selected_query.iter() {
                     if let Ok(holes) = holes_query.get(id) {
                         for hole in &holes.0 {
                             if let Ok(bh) = access.black_hole_query.get(*hole) {
@@ -847,9 +970,23 @@ pub fn command_parser(
                         }
                     }
                 }
+            } else if let (Some(id), Some(lt)) = lt_to_open {
+                if let Ok(holes) = holes_query.get(id) {
+                    for hole in &holes.0 {
+                        if let Ok(bh) = access.black_hole_query.get(*hole) {
+                            if let Ok(wh) = access.white_hole_query.get_mut(bh.wh) {
+                                if wh.link_types.0 == lt {
+                                    access.white_hole_query.get_mut(bh.wh).unwrap().open = true;
+                                }
+                            }
+                        }
+                    }
+                }
             }
-            text.clear();
         }
+        if typed { text.clear(); }
+    }
+    if *mode.get() == Mode::Edit {
         // key commands
         let mut command = text.as_str().split_ascii_whitespace();
         let c0 = command.next();
@@ -1138,6 +1275,16 @@ pub fn command_parser(
                     }
                 }
             }
+            Some("mi") => {
+                *text = midi_in_ports();
+            }
+            Some("mo") => {
+                *text = midi_out_ports();
+            }
+            Some("ep") => {
+                *text = format!(">OSC RECEIVE:\n{}>OSC SEND:\n{}",
+                    access.osc_receiver.list(), access.osc_sender.list());
+            }
             // inspect commands
             Some("ii") => {
                 let mut t = String::new();
@@ -1442,7 +1589,7 @@ pub fn command_parser(
             }
             Some("p") | Some("\"+p") => {
                 #[cfg(not(target_arch = "wasm32"))]
-                if let Ok(string) = access.clipboard.0.get_contents() {
+                if let Some(Ok(string)) = access.clipboard.0.as_mut().map(|c| c.get_contents()) {
                     let _ = access.paste_chan.0.0.try_send(string);
                 }
                 #[cfg(target_arch = "wasm32")]
@@ -1465,6 +1612,27 @@ pub fn command_parser(
                 access.delete_event.send_default();
                 text.clear();
             }
+            // collapse / expand groups
+            Some("zc") | Some("zo") => {
+                for e in access.selected_query.iter() {
+                    if access.group_query.contains(e) {
+                        if c0 == Some("zc") {
+                            commands.entity(e).insert(Collapsed);
+                        } else {
+                            commands.entity(e).remove::<Collapsed>();
+                        }
+                    }
+                }
+                text.clear();
+            }
+            Some("u") => {
+                access.history_event.send(HistoryCommand(false));
+                text.clear();
+            }
+            Some("U") => {
+                access.history_event.send(HistoryCommand(true));
+                text.clear();
+            }
             Some(":help") | Some(":about") | Some("about") | Some("help") => {
                 *text = format!(">see: {}", env!("CARGO_PKG_REPOSITORY"));
             }
@@ -1490,7 +1658,7 @@ never gonna tell a lie and hurt you");
     }
 }
 
-fn str_to_id(s: &str) -> Option<Entity> {
+pub fn str_to_id(s: &str) -> Option<Entity> {
     let mut e = s.split('v');
     if let Some(i) = e.next() {
         if let Some(g) = e.next() {
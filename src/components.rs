@@ -6,20 +6,28 @@ use bevy::{
         reflect::{ReflectComponent, ReflectMapEntities},
     },
     color::Hsla,
+    utils::HashSet,
 };
 
 use fundsp::{
     net::Net,
     shared::Shared,
     slot::Slot,
+    wave::Wave,
 };
 
 use crossbeam_channel::{Sender, Receiver};
 
 use cpal::Stream;
 
+use midir::{MidiInputConnection, MidiOutputConnection};
+
 use copypasta::ClipboardContext;
 
+use std::{sync::Arc, path::PathBuf, time::SystemTime};
+
+use crate::nodes::InputBuffer;
+
 // -------------------- components --------------------
 #[derive(Component, Reflect, Default)]
 #[reflect(Component)]
@@ -89,6 +97,51 @@ pub struct LostWH(pub bool);
 #[reflect(Component)]
 pub struct Save;
 
+// spawned by a patch circle (hidden and not saved)
+#[derive(Component)]
+pub struct Internal(pub Entity);
+
+// on the scene entity of a patch circle's internals until they're loaded
+#[derive(Component)]
+pub struct SubpatchScene(pub Entity);
+
+// parent of the entities an undo/redo just restored, until post_load sets them up
+#[derive(Component)]
+pub struct HistoryScene;
+
+// the file a patch circle loaded, and when it was changed
+#[derive(Component)]
+pub struct Subpatch {
+    pub path: PathBuf,
+    pub modified: Option<SystemTime>,
+}
+
+// (port, circle) of the inlet and outlet circles inside a patch circle
+#[derive(Component, Default)]
+pub struct Ports {
+    pub inlets: Vec<(i8, Entity)>,
+    pub outlets: Vec<(i8, Entity)>,
+}
+
+// published as a parameter by the oscquery server
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Exposed;
+
+// a circle grouping its targets (they move and get colored with it)
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Group;
+
+// a group with its members hidden
+#[derive(Component, Reflect, Default)]
+#[reflect(Component)]
+pub struct Collapsed;
+
+// on the edge of a collapsed group, standing in for a hidden hole
+#[derive(Component)]
+pub struct GroupPort;
+
 #[derive(Component)]
 pub struct Highlight(pub Entity);
 
@@ -180,9 +233,15 @@ pub enum Mode {
 #[reflect(Resource)]
 pub struct Queue(pub Vec<Vec<Entity>>);
 
+// (circle, 0) to process a circle, or (process circle, n) to skip
+// the next n entries when that process circle's gate is closed
 #[derive(Resource, Reflect, Default)]
 #[reflect(Resource)]
-pub struct LoopQueue(pub Vec<Entity>);
+pub struct LoopQueue(pub Vec<(Entity, usize)>);
+
+// circles reading from circles that don't process before them
+#[derive(Resource, Default)]
+pub struct LateCircles(pub HashSet<Entity>);
 
 // initial, final, delta
 #[derive(Resource, Default)]
@@ -195,6 +254,22 @@ pub struct CursorInfo {
 #[derive(Resource)]
 pub struct SlotRes(pub Slot);
 
+// a copy of whatever was last sent to the slot
+#[derive(Resource)]
+pub struct OutNet(pub Net);
+
+// sample rate of the output stream
+#[derive(Resource)]
+pub struct SampleRate(pub f64);
+
+// number of channels of the output stream
+#[derive(Resource)]
+pub struct OutChannels(pub usize);
+
+// (out() circle, channel offset, its input net)
+#[derive(Resource, Default)]
+pub struct OutNets(pub Vec<(Entity, usize, Net)>);
+
 #[derive(Resource, Reflect, Default)]
 #[reflect(Resource)]
 pub struct DragModes {
@@ -220,6 +295,9 @@ impl DragModes {
         self.o = false;
         self.v = false;
     }
+    pub fn any(&self) -> bool {
+        self.t || self.r || self.n || self.h || self.s || self.l || self.a || self.o || self.v
+    }
 }
 
 #[derive(Resource)]
@@ -257,7 +335,8 @@ pub struct TextSize(pub f32);
 pub struct DefaultLT(pub (i8, i8));
 
 #[derive(Resource)]
-pub struct SystemClipboard(pub ClipboardContext);
+// none when there's no clipboard (headless)
+pub struct SystemClipboard(pub Option<ClipboardContext>);
 
 #[derive(Resource, Reflect, Default)]
 #[reflect(Resource)]
@@ -279,26 +358,60 @@ pub struct ConnectionMat(pub Handle<ColorMaterial>);
 #[derive(Resource)]
 pub struct ClickedOnSpace(pub bool);
 
+// running without a window
+#[derive(Resource)]
+pub struct Headless;
+
 pub struct OutStream(pub Stream);
 
 pub struct InStream(pub Stream);
 
+pub struct MidiInConnection(pub MidiInputConnection<()>);
+
+pub struct MidiOutConnection(pub MidiOutputConnection);
+
 #[derive(Resource, Reflect, Default)]
 #[reflect(Resource)]
 pub struct NodeLimit(pub usize);
 
 #[derive(Resource)]
-pub struct InputReceivers(pub Receiver<f32>, pub Receiver<f32>);
+pub struct InputBufferRes(pub Arc<InputBuffer>);
 
 #[derive(Resource)]
 pub struct PasteChannel(pub (Sender<String>, Receiver<String>));
 
+// scene files opened with :theme (only their colors are used)
+#[derive(Resource)]
+pub struct ThemeChannel(pub (Sender<String>, Receiver<String>));
+
+// messages from io tasks, shown in the command line
+#[derive(Resource)]
+pub struct StatusChannel(pub (Sender<String>, Receiver<String>));
+
+// decoded load_wave files (circle, link type, file's sample rate and the wave)
+#[derive(Resource)]
+pub struct WaveChannel(pub (Sender<WaveIn>, Receiver<WaveIn>));
+pub type WaveIn = (Entity, i8, Result<(f32, Wave), String>);
+
+// raw midi messages from the input connection
+#[derive(Resource)]
+pub struct MidiInChannel(pub (Sender<Vec<u8>>, Receiver<Vec<u8>>));
+
+// messages waiting to be sent to the output connection
+#[derive(Resource)]
+pub struct MidiOutChannel(pub (Sender<Vec<u8>>, Receiver<Vec<u8>>));
+
 // -------------------- events --------------------
 #[derive(Event, Default)]
 pub struct OrderChange;
 
+// file, overwrite
 #[derive(Event)]
-pub struct SaveCommand(pub String);
+pub struct SaveCommand(pub String, pub bool);
+
+// write the current preferences to the config file
+#[derive(Event, Default)]
+pub struct SavePrefsCommand;
 
 #[derive(Event, Default)]
 pub struct CopyCommand;
@@ -306,6 +419,21 @@ pub struct CopyCommand;
 #[derive(Event, Default)]
 pub struct DeleteCommand;
 
+// undo (false) or redo (true)
+#[derive(Event)]
+pub struct HistoryCommand(pub bool);
+
+// group (true) or ungroup (false) the selected circles
+#[derive(Event)]
+pub struct GroupCommand(pub bool);
+
+// a command line from osc (/quartz/cmd, /quartz/set/..)
+#[derive(Event)]
+pub struct RemoteCommand(pub String);
+
+#[derive(Event, Default)]
+pub struct AutoOrderCommand;
+
 #[derive(Event, Default)]
 pub struct DacChange;
 
@@ -313,7 +441,22 @@ pub struct DacChange;
 pub struct ConnectCommand(pub Entity);
 
 #[derive(Event)]
-pub struct OutDeviceCommand(pub usize, pub usize, pub Option<u32>, pub Option<u32>);
+pub struct OutDeviceCommand(pub usize, pub usize, pub Option<u32>, pub Option<u32>, pub Option<u16>);
+
+#[derive(Event)]
+pub struct InDeviceCommand(pub usize, pub usize, pub Option<u32>, pub Option<u32>, pub Option<u16>);
+
+// file, duration (seconds), sample rate, bit depth
+#[derive(Event)]
+pub struct RenderCommand(pub String, pub f32, pub Option<u32>, pub Option<u32>);
+
+// start recording to a file, or stop if none
+#[derive(Event)]
+pub struct RecordCommand(pub Option<String>);
+
+// port index, or none for a virtual port
+#[derive(Event)]
+pub struct MidiInCommand(pub Option<usize>);
 
 #[derive(Event)]
-pub struct InDeviceCommand(pub usize, pub usize, pub Option<u32>, pub Option<u32>);
+pub struct MidiOutCommand(pub Option<usize>);
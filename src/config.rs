@@ -0,0 +1,190 @@
+use bevy::{
+    prelude::*,
+    asset::ron,
+    winit::{WinitSettings, UpdateMode},
+    utils::Duration,
+    color::Hsla,
+};
+use serde::{Serialize, Deserialize};
+use std::{fs, path::PathBuf};
+
+use crate::{components::*, cli::Cli};
+
+// user preferences, kept out of scene files
+#[derive(Resource, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Prefs {
+    // colors are [hue, saturation, lightness, alpha]
+    pub clear_color: [f32; 4],
+    pub draw_color: [f32; 4],
+    pub highlight_color: [f32; 4],
+    pub connection_color: [f32; 4],
+    pub command_color: [f32; 4],
+    pub indicator_color: [f32; 4],
+    pub draw_verts: usize,
+    pub connection_width: f32,
+    pub text_size: f32,
+    pub default_lt: (i8, i8),
+    pub node_limit: usize,
+    // letters of the drag modes that are on (e.g. "hsl")
+    pub drag_modes: String,
+    // refresh rates in hz
+    pub focused_rate: f64,
+    pub unfocused_rate: f64,
+    pub out_device: Option<(usize, usize)>,
+    pub in_device: Option<(usize, usize)>,
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+    pub osc_port: Option<u16>,
+}
+
+impl Default for Prefs {
+    fn default() -> Self {
+        Prefs {
+            clear_color: [0., 0., 0., 1.],
+            draw_color: [270., 1., 0.5, 1.],
+            highlight_color: [0., 1., 0.5, 1.],
+            connection_color: [0., 1., 1., 0.7],
+            command_color: [0., 0., 0.7, 1.],
+            indicator_color: [0., 1., 0.5, 0.3],
+            draw_verts: 4,
+            connection_width: 4.,
+            text_size: 0.1,
+            default_lt: (0, 0),
+            node_limit: 500,
+            drag_modes: String::new(),
+            focused_rate: 60.,
+            unfocused_rate: 30.,
+            out_device: None,
+            in_device: None,
+            sample_rate: None,
+            buffer_size: None,
+            channels: None,
+            osc_port: None,
+        }
+    }
+}
+
+// $XDG_CONFIG_HOME/quartz/config.ron or ~/.config/quartz/config.ron
+// (%APPDATA%\quartz\config.ron on windows)
+pub fn prefs_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(dir.join("quartz").join("config.ron"))
+}
+
+impl Prefs {
+    pub fn load() -> Self {
+        let Some(path) = prefs_path() else { return Prefs::default() };
+        let Ok(text) = fs::read_to_string(&path) else { return Prefs::default() };
+        match ron::from_str(&text) {
+            Ok(prefs) => prefs,
+            Err(e) => {
+                // logging isn't set up yet
+                eprintln!("couldn't read {}: {}", path.display(), e);
+                Prefs::default()
+            }
+        }
+    }
+
+    // the command line wins
+    pub fn fill(&self, cli: &mut Cli) {
+        cli.out_device = cli.out_device.or(self.out_device);
+        cli.in_device = cli.in_device.or(self.in_device);
+        cli.sample_rate = cli.sample_rate.or(self.sample_rate);
+        cli.buffer_size = cli.buffer_size.or(self.buffer_size);
+        cli.channels = cli.channels.or(self.channels);
+        cli.osc_port = cli.osc_port.or(self.osc_port);
+    }
+
+    pub fn insert_resources(&self, app: &mut App) {
+        let mut drag_modes = DragModes::default();
+        for c in self.drag_modes.chars() {
+            match c {
+                't' => drag_modes.t = true,
+                'r' => drag_modes.r = true,
+                'n' => drag_modes.n = true,
+                'h' => drag_modes.h = true,
+                's' => drag_modes.s = true,
+                'l' => drag_modes.l = true,
+                'a' => drag_modes.a = true,
+                'o' => drag_modes.o = true,
+                'v' => drag_modes.v = true,
+                _ => {}
+            }
+        }
+        app.insert_resource(ClearColor(hsla(self.clear_color).into()))
+            .insert_resource(DefaultDrawColor(hsla(self.draw_color)))
+            .insert_resource(DefaultDrawVerts(self.draw_verts.clamp(3, 64)))
+            .insert_resource(HighlightColor(hsla(self.highlight_color)))
+            .insert_resource(ConnectionColor(hsla(self.connection_color)))
+            .insert_resource(ConnectionWidth(self.connection_width))
+            .insert_resource(CommandColor(hsla(self.command_color)))
+            .insert_resource(IndicatorColor(hsla(self.indicator_color)))
+            .insert_resource(DefaultLT(self.default_lt))
+            .insert_resource(TextSize(self.text_size))
+            .insert_resource(NodeLimit(self.node_limit))
+            .insert_resource(drag_modes)
+            .insert_resource(WinitSettings {
+                focused_mode: UpdateMode::reactive_low_power(
+                    Duration::from_secs_f64(1.0 / self.focused_rate.max(0.01))
+                ),
+                unfocused_mode: UpdateMode::reactive_low_power(
+                    Duration::from_secs_f64(1.0 / self.unfocused_rate.max(0.01))
+                ),
+            })
+            .insert_resource(self.clone());
+    }
+}
+
+fn hsla(c: [f32; 4]) -> Hsla {
+    Hsla::new(c[0], c[1], c[2], c[3])
+}
+
+fn to_array(c: Hsla) -> [f32; 4] {
+    [c.hue, c.saturation, c.lightness, c.alpha]
+}
+
+fn rate(mode: &UpdateMode) -> f64 {
+    match mode {
+        UpdateMode::Reactive { wait, .. } => 1.0 / wait.as_secs_f64(),
+        UpdateMode::Continuous => 60.,
+    }
+}
+
+pub fn save_prefs(world: &mut World) {
+    let mut prefs = world.resource::<Prefs>().clone();
+    prefs.clear_color = to_array(world.resource::<ClearColor>().0.into());
+    prefs.draw_color = to_array(world.resource::<DefaultDrawColor>().0);
+    prefs.highlight_color = to_array(world.resource::<HighlightColor>().0);
+    prefs.connection_color = to_array(world.resource::<ConnectionColor>().0);
+    prefs.command_color = to_array(world.resource::<CommandColor>().0);
+    prefs.indicator_color = to_array(world.resource::<IndicatorColor>().0);
+    prefs.draw_verts = world.resource::<DefaultDrawVerts>().0;
+    prefs.connection_width = world.resource::<ConnectionWidth>().0;
+    prefs.text_size = world.resource::<TextSize>().0;
+    prefs.default_lt = world.resource::<DefaultLT>().0;
+    prefs.node_limit = world.resource::<NodeLimit>().0;
+    let d = world.resource::<DragModes>();
+    prefs.drag_modes = [
+        (d.t, 't'), (d.r, 'r'), (d.n, 'n'), (d.h, 'h'), (d.s, 's'),
+        (d.l, 'l'), (d.a, 'a'), (d.o, 'o'), (d.v, 'v'),
+    ].iter().filter(|x| x.0).map(|x| x.1).collect();
+    let winit_settings = world.resource::<WinitSettings>();
+    prefs.focused_rate = rate(&winit_settings.focused_mode);
+    prefs.unfocused_rate = rate(&winit_settings.unfocused_mode);
+
+    let Some(path) = prefs_path() else {
+        warn!("don't know where to save preferences");
+        return;
+    };
+    let text = ron::ser::to_string_pretty(&prefs, default()).unwrap();
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, text));
+    match result {
+        Ok(_) => info!("saved preferences to {}", path.display()),
+        Err(e) => warn!("couldn't save preferences to {}: {}", path.display(), e),
+    }
+}
@@ -1,6 +1,6 @@
 use fundsp::hacker32::*;
 use crate::nodes::*;
-use std::num::Wrapping;
+use std::{num::Wrapping, path::{Path, PathBuf}};
 
 pub fn str_to_lt(s: &str) -> i8 {
     if let Ok(n) = s.parse::<i8>() {
@@ -976,17 +976,18 @@ pub fn str_to_op_num(op: &str) -> u16 {
         "butt" => 48,
         "toggle" => 49,
         "key" => 50,
+        "midi_clock" => 97,
+        "midi_s_clock" => 100,
         "rise" => 52,
         "fall" => 53,
         "store" => 54,
-        "num_push" => 55,
+        "push_num" | "num_push" => 55, // num_push is the old name
         "sum" => 56,
         "product" => 57,
         "count" => 58,
         "apply" => 59,
         "render" => 60,
         "var()" => 61,
-        "in()" | "adc()" => 62,
         "monitor()" => 63,
         "timer()" => 64,
         "get()" => 65,
@@ -1014,13 +1015,102 @@ pub fn str_to_op_num(op: &str) -> u16 {
         "&" | "BUS" => 87,
         "^" | "BRA" => 88,
         "!" | "THR" => 89,
-        "out()" | "dac()" => 90,
         x => {
             if x.starts_with("osc_r") { 42 }
             else if x.starts_with("osc_s") { 43 }
+            else if x.starts_with("osc_in(") { 101 }
+            else if x.starts_with("patch ") { 102 }
+            else if x.starts_with("inlet ") { 103 }
+            else if x.starts_with("outlet ") { 104 }
             else if x.starts_with("pressed") { 51 }
+            else if x.starts_with("midi_cc") { 94 }
+            else if x.starts_with("midi_notes") { 96 }
+            else if x.starts_with("midi_note") { 95 }
+            else if x.starts_with("midi_s_note") { 98 }
+            else if x.starts_with("midi_s_cc") { 99 }
             else if x.starts_with("swap") { 91 }
+            else if x.starts_with("rec ") { 92 }
+            else if x.starts_with("load_wave") { 93 }
+            else if x.starts_with("out(") || x.starts_with("dac(") { 90 }
+            else if x.starts_with("in(") || x.starts_with("adc(") { 62 }
             else { 0 }
         }
     }
 }
+
+// scene paths starting with / ./ ../ or ~/ are used as they are
+// anything else is in the assets folder
+pub fn outside_assets(name: &str) -> Option<PathBuf> {
+    if let Some(rest) = name.strip_prefix("~/") {
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+        return Some(Path::new(&home).join(rest));
+    }
+    if Path::new(name).is_absolute() || name.starts_with("./") || name.starts_with("../") {
+        return Some(PathBuf::from(name));
+    }
+    None
+}
+
+pub fn scene_path(name: &str) -> PathBuf {
+    outside_assets(name).unwrap_or(Path::new("assets").join(name))
+}
+
+// the port number of "inlet k" or "outlet k"
+pub fn port_num(op: &str) -> Option<i8> {
+    op.split_ascii_whitespace().nth(1)?.parse::<i8>().ok().filter(|k| *k > 0)
+}
+
+// linear interpolation to another sample rate (so a file plays at the right speed)
+pub fn resample_wave(wave: &Wave, sr: f64) -> Wave {
+    if wave.sample_rate() == sr || wave.len() < 2 { return wave.clone(); }
+    let step = wave.sample_rate() / sr;
+    let len = ((wave.len() - 1) as f64 / step) as usize + 1;
+    let mut out = Wave::new(0, sr);
+    for chan in 0..wave.channels() {
+        let samples: Vec<f32> = (0..len).map(|i| {
+            let t = i as f64 * step;
+            let j = t as usize;
+            let (a, b) = (wave.at(chan, j), wave.at(chan, (j + 1).min(wave.len() - 1)));
+            a + (b - a) * (t - j as f64) as f32
+        }).collect();
+        out.push_channel(&samples);
+    }
+    out
+}
+
+// how many times a process circle runs its targets ("process" or "process n")
+pub fn process_iterations(op: &str) -> Option<usize> {
+    let mut op = op.split_ascii_whitespace();
+    if op.next() != Some("process") { return None; }
+    match op.next() {
+        Some(n) => n.parse::<usize>().ok(),
+        None => Some(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn port_numbers() {
+        assert_eq!(port_num("inlet 1"), Some(1));
+        assert_eq!(port_num("outlet 12"), Some(12));
+        assert_eq!(port_num("inlet"), None);
+        assert_eq!(port_num("inlet 0"), None);
+        assert_eq!(port_num("inlet -2"), None);
+        assert_eq!(port_num("outlet x"), None);
+        assert_eq!(port_num("inlet 300"), None);
+    }
+
+    #[test]
+    fn process_iteration_counts() {
+        assert_eq!(process_iterations("process"), Some(1));
+        assert_eq!(process_iterations("process 4"), Some(4));
+        assert_eq!(process_iterations("process 0"), Some(0));
+        // a count that isn't a number of times makes it not a process circle
+        assert_eq!(process_iterations("process -1"), None);
+        assert_eq!(process_iterations("processor"), None);
+        assert_eq!(process_iterations("sum"), None);
+    }
+}
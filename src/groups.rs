@@ -0,0 +1,268 @@
+use bevy::{
+    prelude::*,
+    render::view::RenderLayers,
+    sprite::Mesh2dHandle,
+    utils::{HashMap, HashSet},
+    color::Hsla,
+};
+
+use fundsp::net::Net;
+
+use crate::components::*;
+
+// make a group circle around the selected circles, or delete the selected groups
+pub fn group_selected(
+    mut commands: Commands,
+    mut events: EventReader<GroupCommand>,
+    selected_query: Query<Entity, With<Selected>>,
+    circle_query: Query<&Transform, With<Order>>, // non-hole circles
+    group_query: Query<(), With<Group>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    default_color: Res<DefaultDrawColor>,
+    default_verts: Res<DefaultDrawVerts>,
+    mut polygon_handles: ResMut<PolygonHandles>,
+    mut delete_event: EventWriter<DeleteCommand>,
+) {
+    for GroupCommand(group) in events.read() {
+        if *group {
+            let members: Vec<Entity> = selected_query.iter().filter(|e| circle_query.contains(*e)).collect();
+            if members.is_empty() { continue; }
+            let mut center = Vec2::ZERO;
+            let mut depth = f32::MAX;
+            for e in &members {
+                let t = circle_query.get(*e).unwrap();
+                center += t.translation.xy();
+                depth = depth.min(t.translation.z);
+            }
+            center /= members.len() as f32;
+            let mut r: f32 = 0.;
+            for e in &members {
+                let t = circle_query.get(*e).unwrap();
+                r = r.max(center.distance(t.translation.xy()) + t.scale.x);
+            }
+            r += 10.;
+            let v = default_verts.0;
+            let mut color = default_color.0;
+            color.alpha *= 0.3;
+            if polygon_handles.0.len() <= v {
+                polygon_handles.0.resize(v + 1, None);
+            }
+            if polygon_handles.0[v].is_none() {
+                let handle = meshes.add(RegularPolygon::new(1., v)).into();
+                polygon_handles.0[v] = Some(handle);
+            }
+            for e in selected_query.iter() {
+                commands.entity(e).remove::<Selected>();
+            }
+            commands.spawn((
+                ColorMesh2dBundle {
+                    mesh: polygon_handles.0[v].clone().unwrap(),
+                    material: materials.add(ColorMaterial::from_color(color)),
+                    transform: Transform {
+                        // behind its members so they can still be clicked
+                        translation: center.extend(depth - 0.01),
+                        scale: Vec3::new(r, r, 1.),
+                        ..default()
+                    },
+                    ..default()
+                },
+                Vertices(v),
+                Col(color),
+                Number(0.),
+                Arr(Vec::new()),
+                Op("group".to_string()),
+                Targets(members),
+                Holes(Vec::new()),
+                Order(0),
+                (
+                    OpNum(0),
+                    Network(Net::new(0,0)),
+                    NetIns(Vec::new()),
+                    OpChanged(false),
+                    GainedWH(false),
+                    LostWH(false),
+                ),
+                RenderLayers::layer(1),
+                Save,
+                Group,
+                Selected,
+            ));
+        } else {
+            // delete only the group circles
+            if !selected_query.iter().any(|e| group_query.contains(e)) { continue; }
+            for e in selected_query.iter() {
+                if !group_query.contains(e) {
+                    commands.entity(e).remove::<Selected>();
+                }
+            }
+            delete_event.send_default();
+        }
+    }
+}
+
+// all circles in a group (including the ones in its member groups)
+fn members(group: Entity, group_query: &Query<(Entity, &Targets, Has<Collapsed>), With<Group>>) -> HashSet<Entity> {
+    let mut members = HashSet::new();
+    let mut stack = vec![group];
+    while let Some(e) = stack.pop() {
+        let Ok((_, targets, _)) = group_query.get(e) else { continue };
+        for t in &targets.0 {
+            if *t != group && members.insert(*t) {
+                stack.push(*t);
+            }
+        }
+    }
+    members
+}
+
+// move and color members with their group, hide the members of collapsed groups
+pub fn update_groups(
+    mut commands: Commands,
+    group_query: Query<(Entity, &Targets, Has<Collapsed>), With<Group>>,
+    mut trans_query: Query<&mut Transform>,
+    mut col_query: Query<&mut Col>,
+    holes_query: Query<&Holes>,
+    bh_query: Query<&BlackHole>,
+    wh_query: Query<&WhiteHole>,
+    selected_query: Query<(), With<Selected>>,
+    arrow_query: Query<&ConnectionArrow>,
+    info_text_query: Query<&InfoText>,
+    port_query: Query<(&Mesh2dHandle, &Handle<ColorMaterial>, &RenderLayers)>,
+    connection_width: Res<ConnectionWidth>,
+    mut last: Local<HashMap<Entity, (Vec3, Hsla)>>,
+    mut hidden: Local<HashSet<Entity>>,
+    mut ports: Local<HashMap<Entity, Entity>>,
+) {
+    let mut seen = HashMap::new();
+    for (e, targets, _) in group_query.iter() {
+        let t = trans_query.get(e).unwrap().translation;
+        let c = col_query.get(e).unwrap().0;
+        // groups we haven't seen before (just made or loaded) don't move anything
+        if let Some((last_t, last_c)) = last.get(&e) {
+            let d = (t - *last_t).xy();
+            for m in &targets.0 {
+                if *m == e || selected_query.contains(*m) { continue; }
+                if d != Vec2::ZERO {
+                    let mut moved = vec![*m];
+                    if let Ok(holes) = holes_query.get(*m) {
+                        moved.extend(holes.0.iter().filter(|h| !selected_query.contains(**h)));
+                    }
+                    for x in moved {
+                        if let Ok(mut t) = trans_query.get_mut(x) {
+                            t.translation.x += d.x;
+                            t.translation.y += d.y;
+                        }
+                    }
+                }
+                if c != *last_c {
+                    if let Ok(mut col) = col_query.get_mut(*m) {
+                        col.0 = Hsla { alpha: col.0.alpha, ..c };
+                    }
+                }
+            }
+        }
+        seen.insert(e, (t, c));
+    }
+    *last = seen;
+
+    // (hidden hole, its group, the hole on the other end)
+    let mut boundary = Vec::new();
+    let mut new_hidden = HashSet::new();
+    for (e, _, collapsed) in group_query.iter() {
+        if !collapsed { continue; }
+        let members = members(e, &group_query);
+        for m in &members {
+            new_hidden.insert(*m);
+            if let Ok(text) = info_text_query.get(*m) { new_hidden.insert(text.0); }
+            let Ok(holes) = holes_query.get(*m) else { continue };
+            for hole in &holes.0 {
+                let (other, other_parent) = if let Ok(bh) = bh_query.get(*hole) {
+                    (bh.wh, bh.wh_parent)
+                } else if let Ok(wh) = wh_query.get(*hole) {
+                    (wh.bh, wh.bh_parent)
+                } else { continue };
+                new_hidden.insert(*hole);
+                if let Ok(text) = info_text_query.get(*hole) { new_hidden.insert(text.0); }
+                if members.contains(&other_parent) {
+                    // inside the group
+                    for x in [*hole, other] {
+                        if let Ok(arrow) = arrow_query.get(x) { new_hidden.insert(arrow.0); }
+                    }
+                } else {
+                    boundary.push((*hole, e, other));
+                }
+            }
+        }
+    }
+    for e in new_hidden.difference(&hidden) {
+        if let Some(mut e) = commands.get_entity(*e) {
+            e.try_insert(Visibility::Hidden);
+            e.remove::<Selected>();
+        }
+    }
+    for e in hidden.difference(&new_hidden) {
+        if let Some(mut ec) = commands.get_entity(*e) {
+            ec.try_insert(Visibility::Inherited);
+        }
+        // so its arrow goes back to it
+        if let Ok(mut t) = trans_query.get_mut(*e) { t.set_changed(); }
+    }
+    *hidden = new_hidden;
+
+    // ports on the group's edge facing the other end of the connection
+    let mut port_trans = HashMap::new();
+    for (hole, group, other) in &boundary {
+        let (Ok(g), Ok(h), Ok(o)) = (trans_query.get(*group), trans_query.get(*hole), trans_query.get(*other)) else {
+            continue;
+        };
+        let dir = (o.translation.xy() - g.translation.xy()).normalize_or_zero();
+        let trans = Transform {
+            translation: (g.translation.xy() + dir * g.scale.x).extend(g.translation.z + 0.001),
+            scale: h.scale,
+            rotation: h.rotation,
+        };
+        port_trans.insert(*hole, trans);
+    }
+    ports.retain(|hole, port| {
+        if port_trans.contains_key(hole) { return true; }
+        if let Some(port) = commands.get_entity(*port) { port.despawn(); }
+        false
+    });
+    for (hole, trans) in &port_trans {
+        if let Some(port) = ports.get(hole) {
+            if let Ok(mut t) = trans_query.get_mut(*port) { *t = *trans; }
+        } else if let Ok((mesh, mat, layers)) = port_query.get(*hole) {
+            let port = commands.spawn((
+                ColorMesh2dBundle {
+                    mesh: mesh.clone(),
+                    material: mat.clone(),
+                    transform: *trans,
+                    ..default()
+                },
+                layers.clone(),
+                GroupPort,
+            )).id();
+            ports.insert(*hole, port);
+        }
+    }
+    // draw the boundary connections from the ports
+    for (hole, _, other) in &boundary {
+        let (bh, wh) = if bh_query.contains(*hole) { (*hole, *other) } else { (*other, *hole) };
+        let end = |e: Entity| port_trans.get(&e).copied().or(trans_query.get(e).ok().copied());
+        let (Some(bh_t), Some(wh_t), Ok(arrow)) = (end(bh), end(wh), arrow_query.get(wh)) else { continue };
+        let bh_trans = bh_t.translation.xy();
+        let wh_trans = wh_t.translation.xy();
+        let perp = (bh_trans - wh_trans).perp();
+        let norm = (wh_trans - bh_trans).normalize_or_zero();
+        let i = wh_trans - wh_t.scale.x * norm;
+        let f = bh_trans + bh_t.scale.x * norm;
+        if let Ok(mut t) = trans_query.get_mut(arrow.0) {
+            *t = Transform {
+                translation: ((i+f) / 2.).extend(100.),
+                scale: Vec3::new(connection_width.0, wh_trans.distance(bh_trans) - (bh_t.scale.x + wh_t.scale.x), 1.),
+                rotation: Quat::from_rotation_z(perp.to_angle()),
+            };
+        }
+    }
+}
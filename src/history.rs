@@ -0,0 +1,290 @@
+use std::any::TypeId;
+
+use bevy::{
+    prelude::*,
+    ecs::{event::ManualEventReader, system::SystemState},
+    reflect::TypeRegistry,
+    utils::{HashMap, HashSet},
+};
+
+use crate::{components::*, functions::*};
+
+// how many steps to keep
+const HISTORY_LIMIT: usize = 50;
+
+// the saved components of an entity
+type EntityState = Vec<Box<dyn Reflect>>;
+// what the entities an edit changed looked like before it (none if they didn't exist)
+type Edit = Vec<(Entity, Option<EntityState>)>;
+
+#[derive(Resource, Default)]
+pub struct History {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    // the saved entities as of the last step
+    known: HashMap<Entity, EntityState>,
+    // the ones that changed since then
+    dirty: HashSet<Entity>,
+}
+
+// what gets remembered (same as what's saved in scene files)
+fn saved_components() -> [TypeId; 15] {
+    [
+        TypeId::of::<Col>(),
+        TypeId::of::<Transform>(),
+        TypeId::of::<Op>(),
+        TypeId::of::<Number>(),
+        TypeId::of::<Arr>(),
+        TypeId::of::<Save>(),
+        TypeId::of::<Order>(),
+        TypeId::of::<BlackHole>(),
+        TypeId::of::<WhiteHole>(),
+        TypeId::of::<Holes>(),
+        TypeId::of::<Vertices>(),
+        TypeId::of::<Targets>(),
+        TypeId::of::<Exposed>(),
+        TypeId::of::<Group>(),
+        TypeId::of::<Collapsed>(),
+    ]
+}
+
+// finds the saved entities that changed since the last time it was used
+#[derive(Resource)]
+pub struct HistoryTracker(SystemState<(
+    Query<'static, 'static, Entity, (With<Save>, Or<(
+        Changed<Col>, Changed<Transform>, Changed<Op>, Changed<Number>, Changed<Arr>,
+        Changed<Save>, Changed<Order>, Changed<BlackHole>, Changed<WhiteHole>, Changed<Holes>,
+        Changed<Vertices>, Changed<Targets>, Changed<Exposed>, Changed<Group>, Changed<Collapsed>,
+    )>)>,
+    RemovedComponents<'static, 'static, Save>,
+    RemovedComponents<'static, 'static, Exposed>,
+    RemovedComponents<'static, 'static, Group>,
+    RemovedComponents<'static, 'static, Collapsed>,
+)>);
+
+impl FromWorld for HistoryTracker {
+    fn from_world(world: &mut World) -> Self {
+        HistoryTracker(SystemState::new(world))
+    }
+}
+
+// has to run every frame so no removal is missed
+fn track(world: &mut World) {
+    let dirty = world.resource_scope(|world, mut tracker: Mut<HistoryTracker>| {
+        let (changed, mut saves, mut exposed, mut groups, mut collapsed) = tracker.0.get_mut(world);
+        let mut dirty: Vec<Entity> = changed.iter().collect();
+        dirty.extend(saves.read().chain(exposed.read()).chain(groups.read()).chain(collapsed.read()));
+        dirty
+    });
+    world.resource_mut::<History>().dirty.extend(dirty);
+}
+
+// none if it's gone (or not saved)
+fn state(world: &World, registry: &TypeRegistry, e: Entity) -> Option<EntityState> {
+    let entity = world.get_entity(e)?;
+    if !entity.contains::<Save>() { return None; }
+    Some(saved_components().into_iter().filter_map(|id| {
+        let component = registry.get_type_data::<ReflectComponent>(id)?;
+        component.reflect(entity).map(|c| c.clone_value())
+    }).collect())
+}
+
+fn same(a: &Option<EntityState>, b: &Option<EntityState>) -> bool {
+    match (a, b) {
+        (None, None) => true,
+        (Some(a), Some(b)) => a.len() == b.len()
+            && a.iter().zip(b).all(|(a, b)| a.reflect_partial_eq(&**b).unwrap_or(false)),
+        _ => false,
+    }
+}
+
+// the changes since the last step (and make now the last step)
+fn pending(world: &mut World) -> Edit {
+    track(world);
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let dirty: Vec<Entity> = world.resource_mut::<History>().dirty.drain().collect();
+    let mut edit = Vec::new();
+    for e in dirty {
+        let now = state(world, &registry, e);
+        let mut history = world.resource_mut::<History>();
+        let before = history.known.remove(&e);
+        if !same(&before, &now) { edit.push((e, before)); }
+        if let Some(now) = now { history.known.insert(e, now); }
+    }
+    edit
+}
+
+fn push(stack: &mut Vec<Edit>, edit: Edit) {
+    stack.push(edit);
+    if stack.len() > HISTORY_LIMIT { stack.remove(0); }
+}
+
+// make whatever changed since the last step undoable
+pub fn record(world: &mut World) {
+    let edit = pending(world);
+    if edit.is_empty() { return; }
+    let mut history = world.resource_mut::<History>();
+    history.redo.clear();
+    push(&mut history.undo, edit);
+}
+
+// a scene that's still being spawned (the last undo, a paste, :e)
+fn loading(world: &mut World) -> bool {
+    let mut scenes = world.query::<&Handle<DynamicScene>>();
+    let mut restores = world.query_filtered::<(), With<HistoryScene>>();
+    !world.resource::<PasteChannel>().0.1.is_empty()
+    || scenes.iter(world).next().is_some()
+    || restores.iter(world).next().is_some()
+}
+
+// whether a click can change the scene (drawing, connecting, or dragging a circle)
+// clicks that only select don't need a step
+fn click_changes_scene(world: &mut World) -> bool {
+    let mode = *world.resource::<State<Mode>>().get();
+    if mode == Mode::Draw { return true; }
+    if mode == Mode::Edit && !world.resource::<DragModes>().any() { return false; }
+    // dragging moves the selected circles wherever the click is
+    let mut selected = world.query_filtered::<(), With<Selected>>();
+    if mode == Mode::Edit && selected.iter(world).next().is_some() { return true; }
+    let mut camera = world.query::<(&Camera, &GlobalTransform)>();
+    let mut windows = world.query::<&Window>();
+    let mut circles = world.query_filtered::<&Transform, (With<Vertices>, With<Save>)>();
+    let Ok((cam, cam_transform)) = camera.get_single(world) else { return false };
+    let Some(cursor) = windows.get_single(world).ok().and_then(|w| w.cursor_position()) else {
+        return false;
+    };
+    let Some(point) = cam.viewport_to_world_2d(cam_transform, cursor) else { return false };
+    circles.iter(world).any(|t| point.distance_squared(t.translation.xy()) < t.scale.x * t.scale.x)
+}
+
+// before anything that might change the scene (a click or running typed commands)
+pub fn take_snapshot(world: &mut World) {
+    track(world);
+    let clicked = world.resource::<ButtonInput<MouseButton>>().just_pressed(MouseButton::Left);
+    let entered = world.resource::<ButtonInput<KeyCode>>()
+        .any_just_pressed([KeyCode::Enter, KeyCode::NumpadEnter]);
+    if !entered && !(clicked && click_changes_scene(world)) { return; }
+    if loading(world) { return; }
+    record(world);
+}
+
+// before commands from osc and the command line
+pub fn record_remote(world: &mut World, mut reader: Local<ManualEventReader<RemoteCommand>>) {
+    if reader.read(world.resource::<Events<RemoteCommand>>()).count() == 0 { return; }
+    if loading(world) { return; }
+    record(world);
+}
+
+fn despawn_saved(world: &mut World, e: Entity) {
+    let Some(entity) = world.get_entity(e) else { return };
+    let arrow = entity.get::<ConnectionArrow>().map(|x| x.0);
+    let text = entity.get::<InfoText>().map(|x| x.0);
+    let highlight = entity.get::<Highlight>().map(|x| x.0);
+    for e in [arrow, text, highlight].into_iter().flatten() {
+        world.despawn(e);
+    }
+    world.despawn(e);
+}
+
+// put the entities of an edit back how they were, returns the edit that undoes that
+// only what differs is touched, so the rest of the patch keeps running
+fn apply(world: &mut World, edit: Edit) -> Edit {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let registry = registry.read();
+    let mut reverse = Vec::new();
+    let mut respawned = Vec::new();
+    let mut order_changed = false;
+    for (e, before) in edit {
+        let now = state(world, &registry, e);
+        let Some(before) = before else {
+            despawn_saved(world, e);
+            world.resource_mut::<History>().known.remove(&e);
+            reverse.push((e, now));
+            continue;
+        };
+        // same id as before (they're used in commands and osc addresses)
+        if world.get_entity(e).is_none() {
+            if world.get_or_spawn(e).is_none() { continue; }
+            respawned.push(e);
+        }
+        let mut op_changed = false;
+        let mut entity = world.entity_mut(e);
+        for id in saved_components() {
+            let Some(component) = registry.get_type_data::<ReflectComponent>(id) else { continue };
+            let is = |c: &&Box<dyn Reflect>| c.get_represented_type_info().map(|i| i.type_id()) == Some(id);
+            let old = before.iter().find(is);
+            let current = now.iter().flatten().find(is);
+            match (old, current) {
+                (Some(old), Some(current)) if current.reflect_partial_eq(&**old) == Some(true) => {}
+                (Some(old), _) => {
+                    component.apply_or_insert(&mut entity, &**old, &registry);
+                    if id == TypeId::of::<Order>() { order_changed = true; }
+                    if id == TypeId::of::<Op>() { op_changed = true; }
+                }
+                (None, Some(_)) => component.remove(&mut entity),
+                (None, None) => {}
+            }
+        }
+        // an existing circle with a different op needs a new node
+        if op_changed && !respawned.contains(&e) {
+            if let Some(op) = entity.get::<Op>().map(|op| op.0.clone()) {
+                entity.insert((Network(str_to_net(&op)), OpNum(str_to_op_num(&op)), OpChanged(true)));
+            }
+        }
+        world.resource_mut::<History>().known.insert(e, before);
+        reverse.push((e, now));
+    }
+    // existing circles getting holes back need their connections set up again
+    let mut circles: Vec<Entity> = respawned.clone();
+    let mut holes_query = world.query::<(Entity, &Holes)>();
+    let owners: Vec<(Entity, Vec<Entity>)> = holes_query.iter(world)
+        .filter(|(c, h)| !respawned.contains(c) && h.0.iter().any(|h| respawned.contains(h)))
+        .map(|(c, h)| (c, h.0.clone()))
+        .collect();
+    for (circle, holes) in owners {
+        for hole in holes.into_iter().filter(|h| !respawned.contains(h)) {
+            let Some(mut hole) = world.get_entity_mut(hole) else { continue };
+            if let Some(arrow) = hole.take::<ConnectionArrow>() {
+                world.despawn(arrow.0);
+            }
+        }
+        circles.push(circle);
+    }
+    if order_changed || !respawned.is_empty() { world.send_event_default::<OrderChange>(); }
+    // post_load sets them up like a loaded scene
+    if !circles.is_empty() {
+        world.spawn(HistoryScene).push_children(&circles);
+    }
+    reverse
+}
+
+pub fn update_history(world: &mut World, mut delete_reader: Local<ManualEventReader<DeleteCommand>>) {
+    // deleting doesn't always come from a click or enter
+    if delete_reader.read(world.resource::<Events<DeleteCommand>>()).count() > 0 {
+        record(world);
+    }
+    let events: Vec<HistoryCommand> = world.resource_mut::<Events<HistoryCommand>>().drain().collect();
+    for HistoryCommand(redo) in events {
+        // the current state isn't all there yet
+        if loading(world) { continue; }
+        let current = pending(world);
+        if redo {
+            // what changed since the last step can be undone after the redo
+            if !current.is_empty() { push(&mut world.resource_mut::<History>().undo, current); }
+            let Some(edit) = world.resource_mut::<History>().redo.pop() else { continue };
+            let reverse = apply(world, edit);
+            push(&mut world.resource_mut::<History>().undo, reverse);
+        } else {
+            // undo what changed since the last step first, if anything did
+            let edit = if current.is_empty() {
+                let Some(edit) = world.resource_mut::<History>().undo.pop() else { continue };
+                edit
+            } else {
+                current
+            };
+            let reverse = apply(world, edit);
+            push(&mut world.resource_mut::<History>().redo, reverse);
+        }
+    }
+}
@@ -6,25 +6,26 @@ use bevy::{
         tonemapping::Tonemapping,
     },
     utils::Duration,
-    winit::{WinitSettings, UpdateMode},
-    tasks::IoTaskPool,
-    scene::{
-        SceneInstance,
-        serde::SceneDeserializer,
+    winit::WinitPlugin,
+    app::ScheduleRunnerPlugin,
+    render::{
+        RenderPlugin,
+        settings::WgpuSettings,
+        view::RenderLayers,
     },
-    asset::ron::Deserializer,
-    render::view::RenderLayers,
+    window::ExitCondition,
+    tasks::IoTaskPool,
+    scene::SceneInstance,
     window::FileDragAndDrop::DroppedFile,
     ecs::system::SystemParam,
+    input::InputSystem,
     sprite::Mesh2dHandle,
-    color::Hsla,
     prelude::*
 };
 
 use bevy_pancam::{PanCam, PanCamPlugin};
 use std::{fs::File, io::Write};
 use copypasta::{ClipboardContext, ClipboardProvider};
-use serde::de::DeserializeSeed;
 
 #[cfg(feature = "inspector")]
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
@@ -39,48 +40,91 @@ mod commands;
 mod nodes;
 mod functions;
 mod osc;
+mod midi;
+mod oscquery;
+mod cli;
+mod config;
+mod scene;
+mod patch;
+mod history;
+mod subpatch;
+mod groups;
 use {components::*, process::*, cursor::*, connections::*,
-     circles::*, audio::*, commands::*, functions::*, osc::*};
+     circles::*, audio::*, commands::*, functions::*, osc::*, midi::*, oscquery::*, cli::*,
+     config::*, scene::*, patch::*, history::*, subpatch::*,
+     groups::*};
 
 fn main() {
     let mut app = App::new();
 
-    app.add_plugins(DefaultPlugins.set(WindowPlugin {
-        primary_window: Some(Window {
-            //transparent: true,
-            title: String::from("awawawa"),
+    let mut cli = Cli::parse();
+    let prefs = Prefs::load();
+    prefs.fill(&mut cli);
+    prefs.insert_resources(&mut app);
+
+    if cli.headless {
+        app.add_plugins(DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                close_when_requested: false,
+            })
+            .set(RenderPlugin {
+                render_creation: WgpuSettings { backends: None, ..default() }.into(),
+                ..default()
+            })
+            .disable::<WinitPlugin>()
+        )
+        .add_plugins(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 60.0)))
+        .insert_resource(Headless);
+    } else {
+        app.add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                //transparent: true,
+                title: cli.title.clone().unwrap_or(String::from("awawawa")),
+                ..default()
+            }),
             ..default()
-        }),
-        ..default()
-    }))
-    .insert_resource(WinitSettings {
-        focused_mode: UpdateMode::reactive_low_power(Duration::from_secs_f64(1.0 / 60.0)),
-        unfocused_mode: UpdateMode::reactive_low_power(Duration::from_secs_f64(1.0 / 30.0)),
-    })
-
-    .add_plugins(PanCamPlugin)
-    .insert_resource(OscSender {
-        host: "127.0.0.1".to_string(),
-        port: 1729
-    })
-    .insert_resource(OscReceiver { socket: None })
-
-    .insert_resource(ClearColor(Color::hsla(0.,0.,0.,1.)))
-    .insert_resource(DefaultDrawColor(Hsla::new(270.,1.,0.5,1.)))
-    .insert_resource(DefaultDrawVerts(4))
-    .insert_resource(HighlightColor(Hsla::new(0.0,1.0,0.5,1.)))
-    .insert_resource(ConnectionColor(Hsla::new(0., 1., 1., 0.7)))
-    .insert_resource(ConnectionWidth(4.))
-    .insert_resource(CommandColor(Hsla::new(0., 0., 0.7, 1.)))
-    .insert_resource(IndicatorColor(Hsla::new(0., 1., 0.5, 0.3)))
-    .insert_resource(DefaultLT((0, 0)))
-    .insert_resource(TextSize(0.1))
+        }))
+        .add_plugins(PanCamPlugin)
+        .add_systems(Update, toggle_pan)
+        .add_systems(Update, update_indicator)
+        .add_systems(Update, file_drag_and_drop)
+        // cursor
+        .add_systems(Update, update_cursor_info)
+        // circles
+        .add_systems(Update, spawn_circles.run_if(in_state(Mode::Draw)))
+        .add_systems(Update, update_selection.after(update_cursor_info).run_if(in_state(Mode::Edit)))
+        .add_systems(Update, move_selected.after(update_selection).run_if(in_state(Mode::Edit)))
+        .add_systems(Update, update_color.after(update_selection).run_if(in_state(Mode::Edit)))
+        .add_systems(Update, update_radius.after(update_selection).run_if(in_state(Mode::Edit)))
+        .add_systems(Update, update_vertices.after(update_selection).run_if(in_state(Mode::Edit)))
+        .add_systems(Update, update_num.after(update_selection).run_if(in_state(Mode::Edit)))
+        .add_systems(Update, open_after_drag.run_if(in_state(Mode::Edit)))
+        .add_systems(Update, rotate_selected.after(update_selection).run_if(in_state(Mode::Edit)))
+        // connections
+        .add_systems(Update, connect.run_if(in_state(Mode::Connect)))
+        .add_systems(Update, target.run_if(in_state(Mode::Connect)));
+    }
+
+    app.insert_resource(cli)
+    .add_systems(Startup, apply_cli)
+    .add_systems(Update, run_cli_command.before(command_parser))
+
+    .init_resource::<OscSender>()
+    .init_resource::<OscReceiver>()
+    .init_resource::<OscQuery>()
+    .add_systems(Update, update_osc_query)
+
     .insert_resource(ClickedOnSpace(true))
-    .insert_resource(NodeLimit(500))
-    .insert_resource(SystemClipboard(ClipboardContext::new().unwrap()))
+    .insert_resource(SystemClipboard(ClipboardContext::new().ok()))
     .insert_resource(Msaa::Sample4)
     .insert_resource(Version(format!("{} {}", env!("CARGO_PKG_VERSION"), env!("COMMIT_HASH"))))
     .insert_resource(PasteChannel(crossbeam_channel::bounded::<String>(1)))
+    .insert_resource(ThemeChannel(crossbeam_channel::unbounded()))
+    .insert_resource(StatusChannel(crossbeam_channel::unbounded()))
+    .insert_resource(WaveChannel(crossbeam_channel::unbounded()))
+    .add_systems(Update, show_status)
     .init_resource::<PolygonHandles>()
 
     .add_systems(Startup, setup)
@@ -90,46 +134,60 @@ fn main() {
     .add_systems(Update, set_out_device)
     .add_systems(Startup, default_in_device)
     .add_systems(Update, set_in_device)
+    .insert_resource(OutNet(fundsp::net::Net::new(0, 2)))
+    .insert_resource(SampleRate(44100.))
+    .insert_resource(OutChannels(2))
+    .init_resource::<OutNets>()
+    .add_systems(Update, render_out.run_if(on_event::<RenderCommand>()))
+    .init_resource::<Recorder>()
+    .add_systems(Update, record.run_if(on_event::<RecordCommand>()))
+    // midi
+    .insert_resource(MidiInChannel(crossbeam_channel::unbounded()))
+    .add_systems(Update, set_midi_in.run_if(on_event::<MidiInCommand>()))
+    .insert_resource(MidiOutChannel(crossbeam_channel::unbounded()))
+    .add_systems(Update, set_midi_out.run_if(on_event::<MidiOutCommand>()))
 
-    .add_systems(Update, toggle_pan)
     .init_state::<Mode>()
     .add_systems(Update, save_scene)
     .add_systems(Update, copy_scene.run_if(on_event::<CopyCommand>()))
+    .add_systems(Update, save_prefs.run_if(on_event::<SavePrefsCommand>()))
     .add_systems(Update, paste_scene)
+    .add_systems(Update, apply_theme)
     .add_systems(Update, post_load)
-    .add_systems(Update, file_drag_and_drop)
-    .add_systems(Update, update_indicator)
-    .init_resource::<DragModes>()
-    // cursor
     .insert_resource(CursorInfo::default())
-    .add_systems(Update, update_cursor_info)
     // circles
-    .add_systems(Update, spawn_circles.run_if(in_state(Mode::Draw)))
-    .add_systems(Update, update_selection.after(update_cursor_info).run_if(in_state(Mode::Edit)))
-    .add_systems(Update, move_selected.after(update_selection).run_if(in_state(Mode::Edit)))
-    .add_systems(Update, update_color.after(update_selection).run_if(in_state(Mode::Edit)))
     .add_systems(Update, update_mat)
-    .add_systems(Update, update_radius.after(update_selection).run_if(in_state(Mode::Edit)))
-    .add_systems(Update, update_vertices.after(update_selection).run_if(in_state(Mode::Edit)))
     .add_systems(Update, update_mesh.after(update_vertices).after(command_parser))
-    .add_systems(Update, update_num.after(update_selection).run_if(in_state(Mode::Edit)))
     .add_systems(Update, highlight_selected.after(delete_selected))
-    .add_systems(Update, open_after_drag.run_if(in_state(Mode::Edit)))
     .add_systems(PreUpdate, transform_highlights)
-    .add_systems(Update, rotate_selected.after(update_selection).run_if(in_state(Mode::Edit)))
     .add_systems(Update, delete_selected.run_if(on_event::<DeleteCommand>()))
+    // undo
+    .init_resource::<History>()
+    .init_resource::<HistoryTracker>()
+    .add_systems(PreUpdate, take_snapshot.after(InputSystem))
+    .add_systems(Update, update_history.after(command_parser).before(delete_selected))
+    // groups
+    .add_systems(Update, group_selected.after(command_parser).before(update_history))
+    .add_systems(Update, update_groups.after(move_selected).after(update_color))
     .add_systems(PreUpdate, update_info_text)
     // events
     .add_event::<SaveCommand>()
+    .add_event::<SavePrefsCommand>()
     .add_event::<CopyCommand>()
     .add_event::<DeleteCommand>()
+    .add_event::<HistoryCommand>()
+    .add_event::<GroupCommand>()
+    .add_event::<RemoteCommand>()
     .add_event::<ConnectCommand>()
     .add_event::<OutDeviceCommand>()
     .add_event::<InDeviceCommand>()
+    .add_event::<RenderCommand>()
+    .add_event::<RecordCommand>()
+    .add_event::<DacChange>()
+    .add_event::<MidiInCommand>()
+    .add_event::<MidiOutCommand>()
     // connections
-    .add_systems(Update, connect.run_if(in_state(Mode::Connect)))
     .add_systems(Update, connect_targets)
-    .add_systems(Update, target.run_if(in_state(Mode::Connect)))
     .add_systems(PreUpdate, update_connection_arrows)
     // order
     .init_resource::<Queue>()
@@ -137,10 +195,22 @@ fn main() {
     .add_event::<OrderChange>()
     .add_systems(PostUpdate, sort_by_order.before(process).run_if(on_event::<OrderChange>()))
     .add_systems(PostUpdate, prepare_loop_queue.after(sort_by_order).before(process))
+    .init_resource::<LateCircles>()
+    .add_event::<AutoOrderCommand>()
+    .add_systems(Update, auto_order.after(command_parser).run_if(on_event::<AutoOrderCommand>()))
+    .add_systems(PostUpdate, check_order.after(sort_by_order))
+    .add_systems(Update, update_order_flags)
+    // subpatches
+    .add_systems(Update, load_subpatches.after(command_parser))
+    .add_systems(PostUpdate, update_ports.before(process))
     // process
     .add_systems(PostUpdate, process)
+    .add_systems(PostUpdate, mix_outputs.after(process))
+    .add_systems(PostUpdate, send_midi_out.after(process))
     // commands
     .add_systems(Update, command_parser)
+    .add_systems(Update, osc_api.before(command_parser))
+    .add_systems(Update, record_remote.after(osc_api).after(run_cli_command).before(command_parser))
 
     // type registry
     .register_type::<DragModes>()
@@ -152,6 +222,9 @@ fn main() {
     .register_type::<Vec<f32>>()
     .register_type::<Selected>()
     .register_type::<Save>()
+    .register_type::<Exposed>()
+    .register_type::<Group>()
+    .register_type::<Collapsed>()
     .register_type::<Order>()
     .register_type::<BlackHole>()
     .register_type::<WhiteHole>()
@@ -186,6 +259,7 @@ fn setup(
     command_color: Res<CommandColor>,
     connection_color: Res<ConnectionColor>,
     indicator_color: Res<IndicatorColor>,
+    headless: Option<Res<Headless>>,
 ) {
     // camera
     commands.spawn((
@@ -214,34 +288,38 @@ fn setup(
         RenderLayers::from_layers(&[0, 1, 2, 3, 4]),
     ));
 
-    // command line
-    commands.spawn(NodeBundle {
-            style: Style {
-                width: Val::Percent(100.0),
-                height: Val::Percent(100.0),
-                justify_content: JustifyContent::SpaceBetween,
+    // command line (no ui without a window, but commands still go through it)
+    if headless.is_some() {
+        commands.spawn((Text::from_section("", TextStyle::default()), CommandText));
+    } else {
+        commands.spawn(NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::SpaceBetween,
+                    ..default()
+                },
                 ..default()
-            },
-            ..default()
-        })
-        .with_children(|parent| {
-            parent.spawn((
-                TextBundle::from_section(
-                    "",
-                    TextStyle {
-                        font_size: 13.0,
-                        color: command_color.0.into(),
+            })
+            .with_children(|parent| {
+                parent.spawn((
+                    TextBundle::from_section(
+                        "",
+                        TextStyle {
+                            font_size: 13.0,
+                            color: command_color.0.into(),
+                            ..default()
+                        },
+                    )
+                    .with_style(Style {
+                        margin: UiRect::all(Val::Px(5.)),
+                        align_self: AlignSelf::End,
                         ..default()
-                    },
-                )
-                .with_style(Style {
-                    margin: UiRect::all(Val::Px(5.)),
-                    align_self: AlignSelf::End,
-                    ..default()
-                }),
-                CommandText,
-            ));
-        });
+                    }),
+                    CommandText,
+                ));
+            });
+    }
 
     // selecting / drawing / connecting indicator
     let id = commands.spawn((
@@ -335,47 +413,70 @@ fn save_scene(world: &mut World) {
     let mut save_events = world.resource_mut::<Events<SaveCommand>>();
     let events: Vec<SaveCommand> = save_events.drain().collect();
     for event in events {
-        let name = event.0;
-        let mut query = world.query_filtered::<Entity, With<Save>>();
-        let scene = DynamicSceneBuilder::from_world(world)
-            .allow::<Col>()
-            .allow::<Transform>()
-            .allow::<Op>()
-            .allow::<Number>()
-            .allow::<Arr>()
-            .allow::<Save>()
-            .allow::<Order>()
-            .allow::<BlackHole>()
-            .allow::<WhiteHole>()
-            .allow::<Holes>()
-            .allow::<Vertices>()
-            .allow::<Targets>()
-            .allow_resource::<DefaultDrawColor>()
-            .allow_resource::<DefaultDrawVerts>()
-            .allow_resource::<HighlightColor>()
-            .allow_resource::<ConnectionColor>()
-            .allow_resource::<ConnectionWidth>()
-            .allow_resource::<ClearColor>()
-            .allow_resource::<CommandColor>()
-            .allow_resource::<IndicatorColor>()
-            .allow_resource::<TextSize>()
-            .allow_resource::<Version>()
-            .allow_resource::<NodeLimit>()
-            .extract_entities(query.iter(world))
-            .extract_resources()
-            .build();
-        let type_registry = world.resource::<AppTypeRegistry>();
-        let type_registry = type_registry.read();
-        let serialized_scene = scene.serialize(&type_registry).unwrap();
+        let SaveCommand(name, overwrite) = event;
+        let path = scene_path(&name);
+        if !overwrite && path.exists() {
+            let _ = world.resource::<StatusChannel>().0.0.send(
+                format!("{} exists (use :w! to overwrite)", path.display())
+            );
+            continue;
+        }
+        // the text format
+        let serialized_scene = if name.ends_with(".patch") {
+            world_to_patch(world)
+        } else {
+            let mut query = world.query_filtered::<Entity, With<Save>>();
+            let scene = DynamicSceneBuilder::from_world(world)
+                .allow::<Col>()
+                .allow::<Transform>()
+                .allow::<Op>()
+                .allow::<Number>()
+                .allow::<Arr>()
+                .allow::<Save>()
+                .allow::<Order>()
+                .allow::<BlackHole>()
+                .allow::<WhiteHole>()
+                .allow::<Holes>()
+                .allow::<Vertices>()
+                .allow::<Targets>()
+                .allow::<Exposed>()
+                .allow::<Group>()
+                .allow::<Collapsed>()
+                // the theme is in the preferences, not the scene
+                .allow_resource::<Version>()
+                .allow_resource::<NodeLimit>()
+                .extract_entities(query.iter(world))
+                .extract_resources()
+                .build();
+            let type_registry = world.resource::<AppTypeRegistry>();
+            let type_registry = type_registry.read();
+            scene.serialize(&type_registry).unwrap()
+        };
 
         #[cfg(not(target_arch = "wasm32"))]
-        IoTaskPool::get()
-            .spawn(async move {
-                File::create(format!("assets/{}", name))
-                    .and_then(|mut file| file.write(serialized_scene.as_bytes()))
-                    .expect("Error while writing scene to file");
-            })
-            .detach();
+        {
+            let status_sender = world.resource::<StatusChannel>().0.0.clone();
+            IoTaskPool::get()
+                .spawn(async move {
+                    // write next to it then rename, so a failed write doesn't eat the old file
+                    let mut tmp = path.clone().into_os_string();
+                    tmp.push(".tmp");
+                    let result = File::create(&tmp)
+                        .and_then(|mut file| {
+                            file.write_all(serialized_scene.as_bytes())?;
+                            file.sync_all()
+                        })
+                        .and_then(|_| std::fs::rename(&tmp, &path));
+                    match result {
+                        Ok(_) => info!("saved {}", path.display()),
+                        Err(e) => {
+                            let _ = std::fs::remove_file(&tmp);
+                            let _ = status_sender.send(format!("couldn't save {}: {}", path.display(), e));
+                        }
+                    }
+                })
+                .detach();
+        }
     }
 }
 
@@ -394,7 +495,13 @@ fn copy_scene(world: &mut World) {
         .allow::<Holes>()
         .allow::<Vertices>()
         .allow::<Targets>()
+        .allow::<Exposed>()
+        .allow::<Group>()
+        .allow::<Collapsed>()
+        // so pasting it somewhere newer can migrate it
+        .allow_resource::<Version>()
         .extract_entities(query.iter(world))
+        .extract_resources()
         .build();
     // FIXME(amy): can we not clone it?
     let type_registry = world.resource::<AppTypeRegistry>().clone();
@@ -402,8 +509,9 @@ fn copy_scene(world: &mut World) {
     let serialized_scene = scene.serialize(&type_registry).unwrap();
     #[cfg(not(target_arch = "wasm32"))]
     {
-        let mut ctx = world.resource_mut::<SystemClipboard>();
-        ctx.0.set_contents(serialized_scene).unwrap();
+        if let Some(ctx) = &mut world.resource_mut::<SystemClipboard>().0 {
+            ctx.set_contents(serialized_scene).unwrap();
+        }
     }
     #[cfg(target_arch = "wasm32")]
     if let Some(window) = web_sys::window() {
@@ -415,35 +523,79 @@ fn copy_scene(world: &mut World) {
 
 fn paste_scene(world: &mut World) {
     if let Ok(string) = world.resource::<PasteChannel>().0.1.try_recv() {
-        let bytes = string.into_bytes();
-        let mut scene = None;
-        if let Ok(mut deserializer) = Deserializer::from_bytes(&bytes) {
-            let type_registry = world.resource::<AppTypeRegistry>();
-            let scene_deserializer = SceneDeserializer {
-                type_registry: &type_registry.read(),
-            };
-            if let Ok(s) = scene_deserializer.deserialize(&mut deserializer) {
-                scene = Some(s);
+        let (string, warning) = migrate(string);
+        if let Some(warning) = warning {
+            let _ = world.resource::<StatusChannel>().0.0.send(warning);
+        }
+        match deserialize_scene(world.resource::<AppTypeRegistry>(), &string) {
+            Ok(mut s) => {
+                // older scenes carry a theme, keep the preferences instead (:theme uses it)
+                // (and the version is only there for migrating)
+                s.resources.retain(|r| r.represents::<NodeLimit>());
+                // so it can be undone
+                record(world);
+                let scene = world.resource_mut::<Assets<DynamicScene>>().add(s);
+                world.spawn(DynamicSceneBundle { scene, ..default() });
+            }
+            Err(e) => {
+                let _ = world.resource::<StatusChannel>().0.0.send(e);
             }
         }
-        if let Some(s) = scene {
-            let scene = world.resource_mut::<Assets<DynamicScene>>().add(s);
-            world.spawn(DynamicSceneBundle { scene, ..default() });
+    }
+}
+
+// take the colors (and other looks) of a scene file, nothing else from it
+fn apply_theme(world: &mut World) {
+    let Ok(string) = world.resource::<ThemeChannel>().0.1.try_recv() else { return };
+    let (string, _) = migrate(string);
+    let status = world.resource::<StatusChannel>().0.0.clone();
+    match deserialize_scene(world.resource::<AppTypeRegistry>(), &string) {
+        Ok(mut s) => {
+            s.entities.clear();
+            s.resources.retain(|r| {
+                r.represents::<ClearColor>()
+                || r.represents::<DefaultDrawColor>()
+                || r.represents::<DefaultDrawVerts>()
+                || r.represents::<HighlightColor>()
+                || r.represents::<ConnectionColor>()
+                || r.represents::<ConnectionWidth>()
+                || r.represents::<CommandColor>()
+                || r.represents::<IndicatorColor>()
+                || r.represents::<TextSize>()
+            });
+            if s.resources.is_empty() {
+                let _ = status.send("that scene has no theme".to_string());
+            } else if let Err(e) = s.write_to_world(world, &mut default()) {
+                let _ = status.send(e.to_string());
+            } else {
+                let _ = status.send("theme applied (:saveprefs to keep it)".to_string());
+            }
+        }
+        Err(e) => {
+            let _ = status.send(e);
         }
     }
 }
 
+fn show_status(
+    status: Res<StatusChannel>,
+    mut command_line_text: Query<&mut Text, With<CommandText>>,
+) {
+    if let Some(msg) = status.0.1.try_iter().last() {
+        warn!("{}", msg);
+        command_line_text.single_mut().sections[0].value = msg;
+    }
+}
+
 fn file_drag_and_drop(
-    mut commands: Commands,
     mut events: EventReader<FileDragAndDrop>,
-    asset_server: Res<AssetServer>,
+    paste_chan: Res<PasteChannel>,
+    status_chan: Res<StatusChannel>,
 ) {
     for event in events.read() {
         if let DroppedFile {path_buf, ..} = event {
-            commands.spawn(DynamicSceneBundle {
-                scene: asset_server.load(path_buf.clone()),
-                ..default()
-            });
+            #[cfg(not(target_arch = "wasm32"))]
+            open_scene(path_buf.clone(), paste_chan.0.0.clone(), status_chan.0.0.clone());
         }
     }
 }
@@ -458,6 +610,9 @@ struct MoreParams<'w, 's> {
     selected_query: Query<'w, 's, Entity, With<Selected>>,
     indicator_color: Res<'w, IndicatorColor>,
     indicator_id: Res<'w, Indicator>,
+    subpatch_scene_query: Query<'w, 's, &'static SubpatchScene>,
+    order_query: Query<'w, 's, &'static mut Order>,
+    history_scene_query: Query<'w, 's, Entity, With<HistoryScene>>,
 }
 
 fn post_load(
@@ -475,13 +630,26 @@ fn post_load(
     mut command_line_text: Query<&mut Text, With<CommandText>>,
     scene_spawner: Res<SceneSpawner>,
     mut polygon_handles: ResMut<PolygonHandles>,
-    more: MoreParams,
+    mut more: MoreParams,
     mut indicator_color_query: Query<&mut Col, Without<Vertices>>,
 ) {
-    for (scene_id, instance_id) in scenes.iter() {
-        if scene_spawner.instance_is_ready(**instance_id) {
-            for e in more.selected_query.iter() {
-                commands.entity(e).remove::<Selected>();
+    // undo/redo restores are already in the world
+    let scenes: Vec<(Entity, bool)> = scenes.iter()
+        .map(|(e, instance_id)| (e, scene_spawner.instance_is_ready(**instance_id)))
+        .chain(more.history_scene_query.iter().map(|e| (e, true)))
+        .collect();
+    for (scene_id, ready) in scenes {
+        if ready {
+            // the internals of a patch circle
+            let parent = more.subpatch_scene_query.get(scene_id).ok().map(|x| x.0);
+            // internals go after the patch circle in the queue
+            let parent_order = parent.and_then(|p| more.order_query.get(p).ok()).map_or(0, |x| x.0);
+            // restores keep the selection they had
+            let restored = more.history_scene_query.contains(scene_id);
+            if parent.is_none() && !restored {
+                for e in more.selected_query.iter() {
+                    commands.entity(e).remove::<Selected>();
+                }
             }
             // update indicator color
             let indicator_color = more.indicator_color.0;
@@ -499,15 +667,24 @@ fn post_load(
                             let handle = meshes.add(RegularPolygon::new(1., v.0)).into();
                             polygon_handles.0[v.0] = Some(handle);
                         }
-                        commands.entity(*child).try_insert((
+                        commands.entity(*child).try_insert(
                             ColorMesh2dBundle {
                                 mesh: polygon_handles.0[v.0].clone().unwrap(),
                                 material: materials.add(ColorMaterial::from_color(c.0)),
                                 transform: *t,
                                 ..default()
                             },
-                            Selected,
-                        ));
+                        );
+                        if let Some(parent) = parent {
+                            // hidden and not saved
+                            commands.entity(*child).try_insert((Internal(parent), RenderLayers::layer(5)));
+                            commands.entity(*child).remove::<Save>();
+                            if let Ok(mut order) = more.order_query.get_mut(*child) {
+                                if order.0 > 0 { order.0 += parent_order; }
+                            }
+                        } else if !restored {
+                            commands.entity(*child).try_insert(Selected);
+                        }
                         if let Ok(op) = op_query.get_mut(*child) {
                             commands.entity(*child).insert((
                                 OpNum(str_to_op_num(&op.0)),
@@ -516,8 +693,10 @@ fn post_load(
                                 OpChanged(true),
                                 GainedWH(false),
                                 LostWH(false),
-                                RenderLayers::layer(1),
                             ));
+                            if parent.is_none() {
+                                commands.entity(*child).insert(RenderLayers::layer(1));
+                            }
                             let holes = &mut holes_query.get_mut(*child).unwrap().0;
                             let mut new_holes = Vec::new();
                             for hole in &mut *holes {
@@ -530,24 +709,32 @@ fn post_load(
                                             transform: Transform::default(),
                                             ..default()
                                         },
-                                        RenderLayers::layer(4),
+                                        RenderLayers::layer(if parent.is_some() { 5 } else { 4 }),
                                         )).id();
                                         commands.entity(*hole).insert((
                                             ConnectionArrow(arrow),
-                                            RenderLayers::layer(3),
+                                            RenderLayers::layer(if parent.is_some() { 5 } else { 3 }),
                                         ));
                                         new_holes.push(*hole);
                                         commands.entity(*hole).remove_parent();
                                     }
                                 } else if let Ok(bh) = black_hole_query.get(*hole) {
                                     if white_hole_query.contains(bh.wh) && main_query.contains(bh.wh_parent) {
-                                        commands.entity(*hole).insert(RenderLayers::layer(2));
+                                        commands.entity(*hole).insert(
+                                            RenderLayers::layer(if parent.is_some() { 5 } else { 2 })
+                                        );
                                         new_holes.push(*hole);
                                         commands.entity(*hole).remove_parent();
                                     }
                                 }
                             }
                             *holes = new_holes;
+                            if let Some(parent) = parent {
+                                // the holes of the internals aren't saved either
+                                for hole in holes.iter() {
+                                    commands.entity(*hole).insert(Internal(parent)).remove::<Save>();
+                                }
+                            }
                             commands.entity(*child).remove_parent();
                         }
                     }
@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection, Ignore};
+#[cfg(unix)]
+use midir::os::unix::{VirtualInput, VirtualOutput};
+
+use crate::components::*;
+
+pub fn set_midi_in(world: &mut World) {
+    let mut events = world.resource_mut::<Events<MidiInCommand>>();
+    let events: Vec<MidiInCommand> = events.drain().collect();
+    for e in events {
+        // drop the old connection first
+        world.remove_non_send_resource::<MidiInConnection>();
+        let Ok(mut midi_in) = MidiInput::new("quartz") else {
+            error!("couldn't create midi input");
+            continue;
+        };
+        // we want clock messages, but not sysex
+        midi_in.ignore(Ignore::SysexAndActiveSense);
+        let sender = world.resource::<MidiInChannel>().0.0.clone();
+        let callback = move |_: u64, message: &[u8], _: &mut ()| {
+            let _ = sender.send(message.to_vec());
+        };
+        let conn = if let Some(i) = e.0 {
+            if let Some(port) = midi_in.ports().get(i) {
+                let name = midi_in.port_name(port).unwrap_or_default();
+                match midi_in.connect(port, "quartz-in", callback, ()) {
+                    Ok(conn) => {
+                        info!("connected to midi input: {}", name);
+                        Some(conn)
+                    }
+                    Err(err) => {
+                        error!("couldn't connect to midi input: {}", err);
+                        None
+                    }
+                }
+            } else {
+                warn!("no midi input port with index {}", i);
+                None
+            }
+        } else {
+            virtual_in(midi_in, callback)
+        };
+        if let Some(conn) = conn {
+            world.insert_non_send_resource(MidiInConnection(conn));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn virtual_in<F>(midi_in: MidiInput, callback: F) -> Option<MidiInputConnection<()>>
+where F: FnMut(u64, &[u8], &mut ()) + Send + 'static
+{
+    match midi_in.create_virtual("quartz-in", callback, ()) {
+        Ok(conn) => Some(conn),
+        Err(err) => {
+            error!("couldn't create virtual midi input: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn virtual_in<F>(_: MidiInput, _: F) -> Option<MidiInputConnection<()>>
+where F: FnMut(u64, &[u8], &mut ()) + Send + 'static
+{
+    warn!("virtual midi ports aren't supported on this platform");
+    None
+}
+
+// list the available input ports
+pub fn midi_in_ports() -> String {
+    let mut text = String::from(">MIDI INPUTS:\n");
+    if let Ok(midi_in) = MidiInput::new("quartz") {
+        for (i, port) in midi_in.ports().iter().enumerate() {
+            let name = midi_in.port_name(port).unwrap_or_default();
+            text += &format!("{}: {}\n", i, name);
+        }
+    }
+    text
+}
+
+pub fn set_midi_out(world: &mut World) {
+    let mut events = world.resource_mut::<Events<MidiOutCommand>>();
+    let events: Vec<MidiOutCommand> = events.drain().collect();
+    for e in events {
+        world.remove_non_send_resource::<MidiOutConnection>();
+        let Ok(midi_out) = MidiOutput::new("quartz") else {
+            error!("couldn't create midi output");
+            continue;
+        };
+        let conn = if let Some(i) = e.0 {
+            if let Some(port) = midi_out.ports().get(i) {
+                let name = midi_out.port_name(port).unwrap_or_default();
+                match midi_out.connect(port, "quartz-out") {
+                    Ok(conn) => {
+                        info!("connected to midi output: {}", name);
+                        Some(conn)
+                    }
+                    Err(err) => {
+                        error!("couldn't connect to midi output: {}", err);
+                        None
+                    }
+                }
+            } else {
+                warn!("no midi output port with index {}", i);
+                None
+            }
+        } else {
+            virtual_out(midi_out)
+        };
+        if let Some(conn) = conn {
+            world.insert_non_send_resource(MidiOutConnection(conn));
+        }
+    }
+}
+
+#[cfg(unix)]
+fn virtual_out(midi_out: MidiOutput) -> Option<MidiOutputConnection> {
+    match midi_out.create_virtual("quartz-out") {
+        Ok(conn) => Some(conn),
+        Err(err) => {
+            error!("couldn't create virtual midi output: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn virtual_out(_: MidiOutput) -> Option<MidiOutputConnection> {
+    warn!("virtual midi ports aren't supported on this platform");
+    None
+}
+
+// send whatever the ops queued this frame
+pub fn send_midi_out(
+    conn: Option<NonSendMut<MidiOutConnection>>,
+    chan: Res<MidiOutChannel>,
+) {
+    let messages = chan.0.1.try_iter();
+    if let Some(mut conn) = conn {
+        for msg in messages {
+            if let Err(err) = conn.0.send(&msg) {
+                warn!("couldn't send midi message: {}", err);
+            }
+        }
+    } else {
+        messages.for_each(drop);
+    }
+}
+
+pub fn midi_out_ports() -> String {
+    let mut text = String::from(">MIDI OUTPUTS:\n");
+    if let Ok(midi_out) = MidiOutput::new("quartz") {
+        for (i, port) in midi_out.ports().iter().enumerate() {
+            let name = midi_out.port_name(port).unwrap_or_default();
+            text += &format!("{}: {}\n", i, name);
+        }
+    }
+    text
+}
+
+// does this status byte belong to the given channel? (1-16, 0 matches any)
+pub fn midi_channel(status: u8, ch: u8) -> bool {
+    ch == 0 || (status & 0x0F) + 1 == ch
+}
+
+// status byte for a channel (1-16) message
+pub fn midi_status(kind: u8, ch: u8) -> u8 {
+    kind | (ch.clamp(1, 16) - 1)
+}
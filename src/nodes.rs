@@ -1,5 +1,9 @@
 use fundsp::hacker32::*;
 use crossbeam_channel::Receiver;
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, AtomicUsize, AtomicBool, Ordering},
+};
 
 /// switch between nets based on index
 /// - input 0: index
@@ -60,8 +64,8 @@ pub struct Seq {
 }
 
 impl Seq {
-    pub fn new(nets: Vec<Net>) -> Self {
-        Seq { nets, events: Vec::new(), sr: 44100. }
+    pub fn new(nets: Vec<Net>, sr: f32) -> Self {
+        Seq { nets, events: Vec::new(), sr }
     }
 }
 
@@ -275,6 +279,8 @@ impl AudioNode for Kr {
     }
 
     fn set_sample_rate(&mut self, sample_rate: f64) {
+        // the net runs at the full rate even though it's ticked every n samples
+        // (that's how kr() patches have always sounded, use sr() to slow it down)
         self.net.set_sample_rate(sample_rate);
     }
 
@@ -297,11 +303,11 @@ pub struct Reset {
 }
 
 impl Reset {
-    pub fn new(net: Net, s: f32) -> Self {
+    pub fn new(net: Net, s: f32, sr: f32) -> Self {
         Reset {
             net,
             dur: s,
-            n: (s * 44100.).round() as usize,
+            n: (s * sr).round() as usize,
             count: 0,
         }
     }
@@ -390,8 +396,8 @@ pub struct ResetV {
 }
 
 impl ResetV {
-    pub fn new(net: Net) -> Self {
-        ResetV { net, count: 0, sr: 44100. }
+    pub fn new(net: Net, sr: f32) -> Self {
+        ResetV { net, count: 0, sr }
     }
 }
 
@@ -468,34 +474,127 @@ impl AudioNode for Ramp {
 }
 
 
-/// node that receives samples from crossbeam channels
-/// - output 0: left
-/// - output 1: right
+/// ring buffer that the input stream writes into. readers keep their
+/// own position, so any number of in() nodes can read the same input
+pub struct InputBuffer {
+    data: Vec<AtomicU32>,
+    channels: usize,
+    frames: usize,
+    sample_rate: f64,
+    // total number of frames written so far
+    written: AtomicUsize,
+    // follow the drift between the input and output clocks
+    pub adaptive: AtomicBool,
+}
+
+impl InputBuffer {
+    pub fn new(channels: usize, sample_rate: f64, adaptive: bool) -> Self {
+        let frames = 8192;
+        InputBuffer {
+            data: (0..channels * frames).map(|_| AtomicU32::new(0)).collect(),
+            channels,
+            frames,
+            sample_rate,
+            written: AtomicUsize::new(0),
+            adaptive: AtomicBool::new(adaptive),
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// write a frame (only the input stream should call this)
+    pub fn push(&self, frame: impl Iterator<Item = f32>) {
+        let written = self.written.load(Ordering::Relaxed);
+        let i = (written % self.frames) * self.channels;
+        for (c, sample) in frame.take(self.channels).enumerate() {
+            self.data[i + c].store(sample.to_bits(), Ordering::Relaxed);
+        }
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    fn get(&self, frame: usize, channel: usize) -> f32 {
+        let i = (frame % self.frames) * self.channels + channel;
+        f32::from_bits(self.data[i].load(Ordering::Relaxed))
+    }
+}
+
+// how far behind the input stream the readers try to stay (in frames)
+const INPUT_LATENCY: f64 = 1024.;
+
+/// node that reads a channel from the input buffer. never blocks, outputs
+/// silence while the buffer fills up, and skips ahead if it falls behind
+/// - output 0: input sample
 #[derive(Clone)]
 pub struct InputNode {
-    lr: Receiver<f32>,
-    rr: Receiver<f32>,
+    buffer: Arc<InputBuffer>,
+    channel: usize,
+    // read position in frames (fractional because of resampling)
+    pos: f64,
+    // input sample rate / output sample rate
+    ratio: f64,
+    // smoothed number of frames available to read
+    fill: f64,
+    filling: bool,
 }
 
 impl InputNode {
-    pub fn new(lr: Receiver<f32>, rr: Receiver<f32>) -> Self {
-        InputNode { lr, rr }
+    pub fn new(buffer: Arc<InputBuffer>, channel: usize, sample_rate: f64) -> Self {
+        let ratio = buffer.sample_rate / sample_rate;
+        InputNode { buffer, channel, pos: 0., ratio, fill: INPUT_LATENCY, filling: true }
     }
 }
 
 impl AudioNode for InputNode {
     const ID: u64 = 1117;
     type Inputs = U0;
-    type Outputs = U2;
+    type Outputs = U1;
 
     #[inline]
     fn tick(
         &mut self,
         _input: &Frame<f32, Self::Inputs>,
     ) -> Frame<f32, Self::Outputs> {
-        let l = self.lr.recv().unwrap_or(0.);
-        let r = self.rr.recv().unwrap_or(0.);
-        [l, r].into()
+        let written = self.buffer.written.load(Ordering::Acquire) as f64;
+        if self.filling {
+            if written < INPUT_LATENCY { return [0.].into(); }
+            self.pos = written - INPUT_LATENCY;
+            self.fill = INPUT_LATENCY;
+            self.filling = false;
+        }
+        let mut available = written - self.pos;
+        // overrun, the stream is about to write over what we haven't read
+        if available > self.buffer.frames as f64 - INPUT_LATENCY {
+            self.pos = written - INPUT_LATENCY;
+            available = INPUT_LATENCY;
+        }
+        // underrun, wait for the buffer to fill up again
+        if available < 2. {
+            self.filling = true;
+            return [0.].into();
+        }
+        let i = self.pos as usize;
+        let frac = self.pos.fract() as f32;
+        let a = self.buffer.get(i, self.channel);
+        let b = self.buffer.get(i + 1, self.channel);
+        let mut step = self.ratio;
+        if self.buffer.adaptive.load(Ordering::Relaxed) {
+            // nudge the read speed to keep the fill level around the target
+            self.fill += (available - self.fill) * 0.0001;
+            let error = (self.fill - INPUT_LATENCY) / INPUT_LATENCY;
+            step *= 1. + (error * 0.001).clamp(-0.005, 0.005);
+        }
+        self.pos += step;
+        [a + (b - a) * frac].into()
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.ratio = self.buffer.sample_rate / sample_rate;
+    }
+
+    fn reset(&mut self) {
+        self.filling = true;
     }
 }
 
@@ -569,3 +668,63 @@ impl AudioUnit for SwapUnit {
         self.x.allocate();
     }
 }
+
+
+/// unit that keeps its net at a fixed sample rate
+/// (sample rate changes from the outside are ignored)
+#[derive(Clone)]
+pub struct FixedSr {
+    x: Net,
+}
+
+impl FixedSr {
+    pub fn new(mut x: Net, sr: f64) -> Self {
+        x.set_sample_rate(sr);
+        Self { x }
+    }
+}
+
+impl AudioUnit for FixedSr {
+    fn reset(&mut self) {
+        self.x.reset();
+    }
+
+    fn set_sample_rate(&mut self, _sample_rate: f64) {}
+
+    fn tick(&mut self, input: &[f32], output: &mut [f32]) {
+        self.x.tick(input, output);
+    }
+
+    fn process(&mut self, size: usize, input: &BufferRef, output: &mut BufferMut) {
+        self.x.process(size, input, output);
+    }
+
+    fn inputs(&self) -> usize {
+        self.x.inputs()
+    }
+
+    fn outputs(&self) -> usize {
+        self.x.outputs()
+    }
+
+    fn route(&mut self, input: &SignalFrame, frequency: f64) -> SignalFrame {
+        self.x.route(input, frequency)
+    }
+
+    fn get_id(&self) -> u64 {
+        const ID: u64 = 1119;
+        ID
+    }
+
+    fn ping(&mut self, probe: bool, hash: AttoHash) -> AttoHash {
+        self.x.ping(probe, hash.hash(self.get_id()))
+    }
+
+    fn footprint(&self) -> usize {
+        core::mem::size_of::<Self>()
+    }
+
+    fn allocate(&mut self) {
+        self.x.allocate();
+    }
+}
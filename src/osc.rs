@@ -1,72 +1,681 @@
-use std::net::UdpSocket;
+use std::{
+    net::{UdpSocket, TcpListener, TcpStream, ToSocketAddrs, SocketAddr},
+    io::{Read, Write},
+    collections::HashMap,
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 use bevy::prelude::*;
+use crossbeam_channel::{bounded, unbounded, Sender, Receiver};
+use fundsp::shared::Shared;
 use rosc::{
     encoder,
     OscMessage,
     OscPacket,
     OscType,
+    OscTime,
     decoder::{decode_udp, MTU},
 };
 
+use crate::{components::*, commands::str_to_id};
+
+type OscIn = (OscMessage, Option<OscTime>);
+// /quartz/... messages and who sent them
+type ApiIn = (OscMessage, Peer);
+type SharedMap = Arc<Mutex<HashMap<String, Vec<Shared>>>>;
+
+// where to send replies to queries
+#[derive(Clone)]
+pub enum Peer {
+    Udp(SocketAddr),
+    // packets for the connection's writer thread
+    Tcp(Sender<Vec<u8>>),
+}
+
+enum OscOut {
+    Udp(String),
+    // tcp streams are slip framed (osc 1.1). packets go to the endpoint's
+    // thread, which does the connecting and sending (is it connected, target)
+    Tcp(Sender<Vec<u8>>, Arc<AtomicBool>, String),
+}
+
 #[derive(Resource)]
 pub struct OscSender {
     pub host: String,
     pub port: u16,
+    // reused for all udp sends
+    socket: Option<UdpSocket>,
+    endpoints: HashMap<String, OscOut>,
+}
+
+impl Default for OscSender {
+    fn default() -> Self {
+        OscSender {
+            host: "127.0.0.1".to_string(),
+            port: 1729,
+            socket: None,
+            endpoints: HashMap::new(),
+        }
+    }
 }
 
 impl OscSender {
-    pub fn send<T, I>(&self, address: &str, args: T)
+    pub fn send<T, I>(&mut self, address: &str, args: T)
     where
         T: IntoIterator<Item = I>,
         I: Into<OscType>,
     {
-        if let Ok(client) = UdpSocket::bind("0.0.0.0:0") {
-            let packet = OscPacket::Message(OscMessage {
-                addr: address.to_string(),
-                args: args.into_iter().map(Into::into).collect(),
-            });
-            let buf = encoder::encode(&packet).unwrap();
-            let _ = client.send_to(&buf, format!("{}:{}", self.host, self.port));
+        self.send_to(None, address, args);
+    }
+
+    // send to a named endpoint, or to host:port if none
+    pub fn send_to<T, I>(&mut self, endpoint: Option<&str>, address: &str, args: T)
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<OscType>,
+    {
+        let packet = OscPacket::Message(OscMessage {
+            addr: address.to_string(),
+            args: args.into_iter().map(Into::into).collect(),
+        });
+        let Ok(buf) = encoder::encode(&packet) else { return };
+        if self.socket.is_none() {
+            self.socket = UdpSocket::bind("0.0.0.0:0").ok();
+        }
+        let target = match endpoint {
+            None => format!("{}:{}", self.host, self.port),
+            Some(name) => match self.endpoints.get_mut(name) {
+                Some(OscOut::Udp(target)) => target.clone(),
+                Some(OscOut::Tcp(sender, _, _)) => {
+                    // dropped if the thread is that far behind
+                    let _ = sender.try_send(buf);
+                    return;
+                }
+                None => return,
+            }
+        };
+        if let Some(socket) = &self.socket {
+            let _ = socket.send_to(&buf, target);
+        }
+    }
+
+    // send straight back to whoever asked (replies to queries)
+    pub fn reply(&mut self, to: &Peer, address: &str, args: Vec<OscType>) {
+        let packet = OscPacket::Message(OscMessage { addr: address.to_string(), args });
+        let Ok(buf) = encoder::encode(&packet) else { return };
+        match to {
+            Peer::Udp(addr) => {
+                if self.socket.is_none() {
+                    self.socket = UdpSocket::bind("0.0.0.0:0").ok();
+                }
+                if let Some(socket) = &self.socket {
+                    let _ = socket.send_to(&buf, addr);
+                }
+            }
+            Peer::Tcp(sender) => { let _ = sender.send(buf); }
+        }
+    }
+
+    pub fn add_endpoint(&mut self, name: &str, host: &str, port: u16, tcp: bool) {
+        let target = format!("{}:{}", host, port);
+        let out = if tcp {
+            let (sender, packets) = bounded(1024);
+            let connected = Arc::new(AtomicBool::new(false));
+            let (t, c) = (target.clone(), connected.clone());
+            thread::spawn(move || send_tcp(t, packets, c));
+            OscOut::Tcp(sender, connected, target)
+        } else {
+            OscOut::Udp(target)
+        };
+        self.endpoints.insert(name.to_string(), out);
+    }
+
+    pub fn remove_endpoint(&mut self, name: &str) {
+        self.endpoints.remove(name);
+    }
+
+    pub fn list(&self) -> String {
+        let mut text = String::new();
+        for (name, out) in &self.endpoints {
+            match out {
+                OscOut::Udp(target) => text += &format!("    {}: {} udp\n", name, target),
+                OscOut::Tcp(_, connected, target) => {
+                    let state = if connected.load(Ordering::Relaxed) { "" } else { " (disconnected)" };
+                    text += &format!("    {}: {} tcp{}\n", name, target, state);
+                }
+            }
         }
+        text
     }
 }
 
 #[derive(Resource)]
 pub struct OscReceiver {
-    pub socket: Option<UdpSocket>,
+    // messages for the osc_r circles (with the time tag of their bundle)
+    pub messages: Receiver<OscIn>,
+    sender: Sender<OscIn>,
+    // messages for the remote control api
+    pub api: Receiver<ApiIn>,
+    api_sender: Sender<ApiIn>,
+    // osc_in() values by address. only the receiver threads and the
+    // main thread touch the map, the audio thread just reads the shared values
+    pub shared: SharedMap,
+    // listening threads by endpoint name (the osc op's is "")
+    endpoints: HashMap<String, (u16, bool, Arc<AtomicBool>)>,
+}
+
+impl Default for OscReceiver {
+    fn default() -> Self {
+        let (sender, messages) = bounded(1024);
+        let (api_sender, api) = bounded(1024);
+        OscReceiver {
+            messages,
+            sender,
+            api,
+            api_sender,
+            shared: default(),
+            endpoints: HashMap::new(),
+        }
+    }
 }
 
 impl OscReceiver {
     pub fn init(&mut self, port: u16) {
-        if let Ok(socket) = UdpSocket::bind(format!("0.0.0.0:{}", port)) {
-            socket.set_nonblocking(true).unwrap();
-            self.socket = Some(socket);
+        self.listen("", port, false);
+    }
+
+    pub fn listen(&mut self, name: &str, port: u16, tcp: bool) {
+        if let Some((p, t, running)) = self.endpoints.get(name) {
+            if *p == port && *t == tcp && running.load(Ordering::Relaxed) { return; }
+        }
+        self.close(name);
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let senders = Senders {
+            shared: self.shared.clone(),
+            messages: self.sender.clone(),
+            api: self.api_sender.clone(),
+        };
+        let ok = if tcp {
+            listen_tcp(port, r, senders)
+        } else {
+            listen_udp(port, r, senders)
+        };
+        if ok {
+            self.endpoints.insert(name.to_string(), (port, tcp, running));
         } else {
             warn!("can't bind! another app is using port {}", port);
-            self.socket = None;
         }
     }
+
+    pub fn close(&mut self, name: &str) {
+        if let Some((_, _, running)) = self.endpoints.remove(name) {
+            running.store(false, Ordering::Relaxed);
+        }
+    }
+
+    pub fn list(&self) -> String {
+        let mut text = String::new();
+        for (name, (port, tcp, _)) in &self.endpoints {
+            let name = if name.is_empty() { "(osc op)" } else { name };
+            text += &format!("    {}: {} {}\n", name, port, if *tcp { "tcp" } else { "udp" });
+        }
+        text
+    }
+
+    // port for clients that need to know one (the osc op's first)
+    pub fn udp_port(&self) -> Option<u16> {
+        if let Some((port, false, _)) = self.endpoints.get("") { return Some(*port); }
+        self.endpoints.values().find(|(_, tcp, _)| !tcp).map(|(port, _, _)| *port)
+    }
+
+    // the shared values of an address (at least n of them)
+    pub fn shared(&self, address: &str, n: usize) -> Vec<Shared> {
+        let mut map = self.shared.lock().unwrap();
+        let vars = map.entry(address.to_string()).or_default();
+        while vars.len() < n { vars.push(Shared::new(0.)); }
+        vars[..n].to_vec()
+    }
+}
+
+// a tcp send endpoint's thread. connects, sends, and reconnects when the
+// connection is lost (at most once a second). ends when the endpoint is removed
+fn send_tcp(target: String, packets: Receiver<Vec<u8>>, connected: Arc<AtomicBool>) {
+    let connect = || {
+        let stream = target.to_socket_addrs().ok()
+            .and_then(|mut addrs| addrs.next())
+            .and_then(|addr| TcpStream::connect_timeout(&addr, Duration::from_secs(1)).ok());
+        // so a stalled peer can't hold the thread forever
+        if let Some(s) = &stream { let _ = s.set_write_timeout(Some(Duration::from_secs(1))); }
+        stream
+    };
+    let mut stream = connect();
+    if stream.is_none() { warn!("couldn't connect to {}", target); }
+    connected.store(stream.is_some(), Ordering::Relaxed);
+    let mut last_try = Instant::now();
+    for buf in packets {
+        if stream.is_none() && last_try.elapsed() >= Duration::from_secs(1) {
+            last_try = Instant::now();
+            stream = connect();
+            if stream.is_some() { info!("reconnected to {}", target); }
+        }
+        if let Some(s) = &mut stream {
+            if s.write_all(&slip_encode(&buf)).is_err() {
+                warn!("lost tcp connection to {}", target);
+                stream = None;
+                last_try = Instant::now();
+            }
+        }
+        connected.store(stream.is_some(), Ordering::Relaxed);
+    }
+}
+
+// where the listening threads put what they receive
+#[derive(Clone)]
+struct Senders {
+    shared: SharedMap,
+    messages: Sender<OscIn>,
+    api: Sender<ApiIn>,
+}
+
+fn listen_udp(port: u16, running: Arc<AtomicBool>, senders: Senders) -> bool {
+    let Ok(socket) = UdpSocket::bind(format!("0.0.0.0:{}", port)) else { return false };
+    // so the thread can notice when it's stopped
+    socket.set_read_timeout(Some(Duration::from_millis(100))).unwrap();
+    thread::spawn(move || {
+        let mut buf = [0u8; MTU];
+        while running.load(Ordering::Relaxed) {
+            let Ok((num_bytes, from)) = socket.recv_from(&mut buf) else { continue };
+            dispatch(&buf[0..num_bytes], &Peer::Udp(from), &senders);
+        }
+    });
+    true
 }
 
-pub fn unpacket(packet: OscPacket, buffer: &mut Vec<OscMessage>) {
+fn listen_tcp(port: u16, running: Arc<AtomicBool>, senders: Senders) -> bool {
+    let Ok(listener) = TcpListener::bind(format!("0.0.0.0:{}", port)) else { return false };
+    listener.set_nonblocking(true).unwrap();
+    thread::spawn(move || {
+        while running.load(Ordering::Relaxed) {
+            let Ok((mut stream, _)) = listener.accept() else {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            };
+            let _ = stream.set_nonblocking(false);
+            let _ = stream.set_read_timeout(Some(Duration::from_millis(100)));
+            let (running, senders) = (running.clone(), senders.clone());
+            // replies are written on their own thread, the reader's stream stays read-only
+            let (reply, replies) = unbounded::<Vec<u8>>();
+            if let Ok(mut out) = stream.try_clone() {
+                thread::spawn(move || {
+                    for buf in replies {
+                        if out.write_all(&slip_encode(&buf)).is_err() { break; }
+                    }
+                });
+            }
+            thread::spawn(move || {
+                let peer = Peer::Tcp(reply);
+                let mut buf = [0u8; 4096];
+                let mut slip = SlipDecoder::default();
+                while running.load(Ordering::Relaxed) {
+                    match stream.read(&mut buf) {
+                        // closed
+                        Ok(0) => break,
+                        Ok(n) => {
+                            for b in &buf[..n] {
+                                if let Some(frame) = slip.push(*b) {
+                                    dispatch(&frame, &peer, &senders);
+                                }
+                            }
+                        }
+                        Err(e) if matches!(e.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+    });
+    true
+}
+
+fn dispatch(buf: &[u8], from: &Peer, senders: &Senders) {
+    let Ok((_, packet)) = decode_udp(buf) else { return };
+    let mut messages = Vec::new();
+    unpacket(packet, None, &mut messages);
+    // if nobody is reading we just drop them
+    for msg in messages {
+        if msg.0.addr.starts_with("/quartz/") {
+            let _ = senders.api.try_send((msg.0, from.clone()));
+        } else {
+            set_shared(&senders.shared, &msg.0);
+            let _ = senders.messages.try_send(msg);
+        }
+    }
+}
+
+// what /quartz/cmd can run
+const REMOTE_COMMANDS: &[&str] = &[
+    ":set", "set", ":delta", "delta", ":push", "push", ":lt", "lt", ":ht", "ht",
+    ":dc", "dc", ":dv", "dv", ":tsel", ":ex", ":group", ":ungroup", ":autoorder",
+];
+
+// remote control. /quartz/cmd and /quartz/set/.. become commands (run like
+// typed ones, in any mode), /quartz/query/.. gets a reply with the same address
+pub fn osc_api(
+    receiver: Res<OscReceiver>,
+    mut sender: ResMut<OscSender>,
+    mut remote: EventWriter<RemoteCommand>,
+    status: Res<StatusChannel>,
+    circles: Query<Entity, With<Vertices>>,
+    num_query: Query<&Number>,
+    arr_query: Query<&Arr>,
+    op_query: Query<&Op>,
+    order_query: Query<&Order>,
+    trans_query: Query<&Transform>,
+    col_query: Query<&Col>,
+    vertices_query: Query<&Vertices>,
+    targets_query: Query<&Targets>,
+) {
+    let mut lines = Vec::new();
+    for (msg, from) in receiver.api.try_iter() {
+        let args: Vec<String> = msg.args.iter().filter_map(|arg| match arg {
+            OscType::String(s) => Some(s.clone()),
+            OscType::Float(f) => Some(f.to_string()),
+            OscType::Double(d) => Some(d.to_string()),
+            OscType::Int(i) => Some(i.to_string()),
+            OscType::Long(l) => Some(l.to_string()),
+            _ => None,
+        }).collect();
+        if msg.addr == "/quartz/cmd" {
+            // anyone on the network can send these, so nothing that touches
+            // files, devices, or quits
+            for line in args.iter().flat_map(|a| a.split(';')) {
+                let c0 = line.split_ascii_whitespace().next().unwrap_or_default();
+                if REMOTE_COMMANDS.contains(&c0) {
+                    lines.push(line.to_string());
+                } else if !c0.is_empty() {
+                    let _ = status.0.0.send(format!("{} can't be run over osc", c0));
+                }
+            }
+        } else if let Some(property) = msg.addr.strip_prefix("/quartz/set/") {
+            lines.push(format!(":set {} {}", property, args.join(" ")));
+        } else if let Some(id) = msg.addr.strip_prefix("/quartz/n/") {
+            // exposed parameters (oscquery)
+            lines.push(format!(":set n {} {}", id, args.join(" ")));
+        } else if let Some(id) = msg.addr.strip_prefix("/quartz/arr/") {
+            lines.push(format!(":set arr {} {}", id, args.join(" ")));
+        } else if let Some(property) = msg.addr.strip_prefix("/quartz/query/") {
+            if property == "ids" {
+                let ids = circles.iter().map(|e| OscType::String(e.to_string())).collect();
+                sender.reply(&from, &msg.addr, ids);
+                continue;
+            }
+            let Some(e) = args.first().and_then(|s| str_to_id(s)) else { continue };
+            let t = trans_query.get(e).ok();
+            let c = col_query.get(e).ok().map(|c| c.0);
+            let values: Option<Vec<OscType>> = match property {
+                "n" => num_query.get(e).ok().map(|n| vec![n.0.into()]),
+                "arr" => arr_query.get(e).ok().map(|a| a.0.iter().map(|x| (*x).into()).collect()),
+                "op" => op_query.get(e).ok().map(|op| vec![op.0.clone().into()]),
+                "ord" | "order" => order_query.get(e).ok().map(|o| vec![(o.0 as i32).into()]),
+                "x" => t.map(|t| vec![t.translation.x.into()]),
+                "y" => t.map(|t| vec![t.translation.y.into()]),
+                "z" => t.map(|t| vec![t.translation.z.into()]),
+                "r" => t.map(|t| vec![t.scale.x.into()]),
+                "rot" => t.map(|t| vec![t.rotation.to_euler(EulerRot::XYZ).2.into()]),
+                "h" => c.map(|c| vec![c.hue.into()]),
+                "s" => c.map(|c| vec![c.saturation.into()]),
+                "l" => c.map(|c| vec![c.lightness.into()]),
+                "a" => c.map(|c| vec![c.alpha.into()]),
+                "v" => vertices_query.get(e).ok().map(|v| vec![(v.0 as i32).into()]),
+                "tar" | "targets" => targets_query.get(e).ok()
+                    .map(|t| t.0.iter().map(|e| OscType::String(e.to_string())).collect()),
+                _ => None,
+            };
+            if let Some(values) = values {
+                let mut reply = vec![OscType::String(e.to_string())];
+                reply.extend(values);
+                sender.reply(&from, &msg.addr, reply);
+            }
+        }
+    }
+    remote.send_batch(lines.into_iter().map(RemoteCommand));
+}
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+// double-ended slip, like osc 1.1 says
+fn slip_encode(buf: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(buf.len() + 2);
+    out.push(SLIP_END);
+    for b in buf {
+        match *b {
+            SLIP_END => out.extend([SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => out.extend([SLIP_ESC, SLIP_ESC_ESC]),
+            b => out.push(b),
+        }
+    }
+    out.push(SLIP_END);
+    out
+}
+
+#[derive(Default)]
+struct SlipDecoder {
+    frame: Vec<u8>,
+    esc: bool,
+}
+
+impl SlipDecoder {
+    // returns a frame when one is complete
+    fn push(&mut self, b: u8) -> Option<Vec<u8>> {
+        if self.esc {
+            self.esc = false;
+            match b {
+                SLIP_ESC_END => self.frame.push(SLIP_END),
+                SLIP_ESC_ESC => self.frame.push(SLIP_ESC),
+                b => self.frame.push(b),
+            }
+        } else {
+            match b {
+                SLIP_END if !self.frame.is_empty() => return Some(std::mem::take(&mut self.frame)),
+                SLIP_END => {}
+                SLIP_ESC => self.esc = true,
+                b => self.frame.push(b),
+            }
+        }
+        None
+    }
+}
+
+fn set_shared(shared: &SharedMap, msg: &OscMessage) {
+    if let Ok(map) = shared.lock() {
+        if let Some(vars) = map.get(&msg.addr) {
+            for (var, arg) in vars.iter().zip(&msg.args) {
+                match arg {
+                    OscType::Float(f) => var.set_value(*f),
+                    OscType::Double(d) => var.set_value(*d as f32),
+                    OscType::Int(i) => var.set_value(*i as f32),
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+pub fn unpacket(packet: OscPacket, time: Option<OscTime>, buffer: &mut Vec<(OscMessage, Option<OscTime>)>) {
     match packet {
         OscPacket::Message(msg) => {
-            buffer.push(msg);
+            buffer.push((msg, time));
         }
         OscPacket::Bundle(bundle) => {
             bundle.content.iter().for_each(|packet| {
-                unpacket(packet.clone(), buffer);
+                unpacket(packet.clone(), Some(bundle.timetag), buffer);
             });
         }
     }
 }
 
-pub fn receive_packet(socket: &UdpSocket) -> Option<OscPacket> {
-    let mut buf = [0u8; MTU];
-    if let Ok(num_bytes) = socket.recv(&mut buf) {
-        if let Ok((_, packet)) = decode_udp(&buf[0..num_bytes]) {
-            return Some(packet);
+// seconds from now until the time tag (negative if it's in the past)
+pub fn time_from_now(time: OscTime) -> f32 {
+    // 1 means "immediately"
+    if time.seconds == 0 && time.fractional == 1 { return 0.; }
+    let time = SystemTime::from(time);
+    match time.duration_since(SystemTime::now()) {
+        Ok(d) => d.as_secs_f32(),
+        Err(e) => -e.duration().as_secs_f32(),
+    }
+}
+
+// the addresses of an osc_r circle. patterns are matched, plain addresses
+// match any address that's part of them (like before patterns, /touch1 gets /touch too)
+pub fn osc_r_match(addresses: &str, address: &str) -> bool {
+    addresses.split_ascii_whitespace().any(|p| {
+        if p.contains(['*', '?', '[', '{']) { osc_match(p, address) } else { p.contains(address) }
+    })
+}
+
+// osc 1.0 address pattern matching (*, ?, [], {})
+pub fn osc_match(pattern: &str, address: &str) -> bool {
+    let p: Vec<&str> = pattern.split('/').collect();
+    let a: Vec<&str> = address.split('/').collect();
+    p.len() == a.len() && p.iter().zip(a).all(|(p, a)| match_part(p.as_bytes(), a.as_bytes()))
+}
+
+fn match_part(p: &[u8], a: &[u8]) -> bool {
+    match p.first() {
+        None => a.is_empty(),
+        Some(b'*') => (0..=a.len()).any(|i| match_part(&p[1..], &a[i..])),
+        Some(b'?') => !a.is_empty() && match_part(&p[1..], &a[1..]),
+        Some(b'[') => {
+            let Some(end) = p.iter().position(|&c| c == b']') else { return false };
+            let Some(&c) = a.first() else { return false };
+            let mut set = &p[1..end];
+            let negate = set.first() == Some(&b'!');
+            if negate { set = &set[1..]; }
+            let mut found = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i+1] == b'-' {
+                    if set[i] <= c && c <= set[i+2] { found = true; }
+                    i += 3;
+                } else {
+                    if set[i] == c { found = true; }
+                    i += 1;
+                }
+            }
+            found != negate && match_part(&p[end+1..], &a[1..])
+        }
+        Some(b'{') => {
+            let Some(end) = p.iter().position(|&c| c == b'}') else { return false };
+            let rest = &p[end+1..];
+            p[1..end].split(|&c| c == b',')
+                .any(|alt| a.starts_with(alt) && match_part(rest, &a[alt.len()..]))
+        }
+        Some(&c) => a.first() == Some(&c) && match_part(&p[1..], &a[1..]),
+    }
+}
+
+// flatten the args into numbers. strings are hashed (and collected separately)
+// so they keep their place among the numbers
+pub fn osc_args(args: &[OscType], arr: &mut Vec<f32>, strings: &mut Vec<String>) {
+    for arg in args {
+        match arg {
+            OscType::Float(f) => arr.push(*f),
+            OscType::Double(d) => arr.push(*d as f32),
+            OscType::Int(i) => arr.push(*i as f32),
+            OscType::Long(l) => arr.push(*l as f32),
+            OscType::Bool(b) => arr.push(if *b { 1. } else { 0. }),
+            OscType::Char(c) => arr.push(*c as u32 as f32),
+            OscType::Time(t) => arr.push(time_from_now(*t)),
+            OscType::Blob(b) => arr.extend(b.iter().map(|x| *x as f32)),
+            OscType::Color(c) => arr.extend([c.red, c.green, c.blue, c.alpha].map(|x| x as f32)),
+            OscType::Midi(m) => arr.extend([m.port, m.status, m.data1, m.data2].map(|x| x as f32)),
+            OscType::String(s) => {
+                arr.push(str_hash(s));
+                strings.push(s.clone());
+            }
+            OscType::Array(a) => osc_args(&a.content, arr, strings),
+            OscType::Nil | OscType::Inf => {}
         }
     }
-    None
+}
+
+// fnv-1a, cut to 24 bits so it fits exactly in an f32. only good for telling
+// a few known strings apart, different strings can collide (about 1 in 16 million
+// per pair, likely once there are a few thousand of them)
+fn str_hash(s: &str) -> f32 {
+    let mut h: u32 = 0x811c9dc5;
+    for b in s.bytes() {
+        h ^= b as u32;
+        h = h.wrapping_mul(0x01000193);
+    }
+    (h & 0xFFFFFF) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_patterns() {
+        assert!(osc_match("/a/b", "/a/b"));
+        assert!(!osc_match("/a/b", "/a/b/c"));
+        assert!(osc_match("/a/*", "/a/anything"));
+        assert!(osc_match("/a/x*y", "/a/xy"));
+        assert!(!osc_match("/a/x*y", "/a/xyz"));
+        assert!(osc_match("/a/?", "/a/b"));
+        assert!(!osc_match("/a/?", "/a/bc"));
+        assert!(osc_match("/ch/[1-3]", "/ch/2"));
+        assert!(!osc_match("/ch/[1-3]", "/ch/4"));
+        assert!(osc_match("/ch/[!1-3]", "/ch/4"));
+        assert!(osc_match("/{foo,bar}/x", "/bar/x"));
+        assert!(!osc_match("/{foo,bar}/x", "/baz/x"));
+        // broken patterns don't match anything
+        assert!(!osc_match("/ch/[1-3", "/ch/2"));
+    }
+
+    #[test]
+    fn osc_r_addresses() {
+        assert!(osc_r_match("/touch1 /touch3", "/touch3"));
+        assert!(osc_r_match("/gyroscope", "/gyro"));
+        assert!(!osc_r_match("/gyro", "/gyroscope"));
+        assert!(osc_r_match("/fader* /x", "/fader2"));
+        // a pattern isn't matched as a substring
+        assert!(!osc_r_match("/fader*", "/fade"));
+        assert!(!osc_r_match("/1/push[1-4]", "/1/push"));
+    }
+
+    fn decode(bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut slip = SlipDecoder::default();
+        bytes.iter().filter_map(|b| slip.push(*b)).collect()
+    }
+
+    #[test]
+    fn slip_round_trip() {
+        let frame = vec![1, SLIP_END, 2, SLIP_ESC, 3, SLIP_ESC_END];
+        let encoded = slip_encode(&frame);
+        assert_eq!(encoded.first(), Some(&SLIP_END));
+        assert_eq!(encoded.last(), Some(&SLIP_END));
+        assert_eq!(encoded.iter().filter(|b| **b == SLIP_END).count(), 2);
+        assert_eq!(decode(&encoded), vec![frame]);
+    }
+
+    #[test]
+    fn slip_frames_split_anywhere() {
+        let mut bytes = slip_encode(&[1, 2, 3]);
+        bytes.extend(slip_encode(&[SLIP_END]));
+        // the ends between frames don't make empty frames
+        assert_eq!(decode(&bytes), vec![vec![1, 2, 3], vec![SLIP_END]]);
+        let mut slip = SlipDecoder::default();
+        let (a, b) = bytes.split_at(3);
+        let mut frames: Vec<Vec<u8>> = a.iter().filter_map(|x| slip.push(*x)).collect();
+        assert!(frames.is_empty());
+        frames.extend(b.iter().filter_map(|x| slip.push(*x)));
+        assert_eq!(frames.len(), 2);
+    }
 }
@@ -0,0 +1,201 @@
+use std::{
+    collections::BTreeMap,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}},
+    thread,
+    time::Duration,
+};
+use bevy::prelude::*;
+
+use crate::{components::*, osc::*};
+
+// a node in the osc address space
+#[derive(Default)]
+pub struct OscNode {
+    children: BTreeMap<String, OscNode>,
+    // (values, access (1 read, 2 write, 3 both), description)
+    method: Option<(Vec<f32>, u8, String)>,
+}
+
+impl OscNode {
+    fn insert(&mut self, path: &str, values: Vec<f32>, access: u8, description: String) {
+        let mut node = self;
+        for part in path.split('/').filter(|s| !s.is_empty()) {
+            node = node.children.entry(part.to_string()).or_default();
+        }
+        node.method = Some((values, access, description));
+    }
+
+    fn get(&self, path: &str) -> Option<&OscNode> {
+        let mut node = self;
+        for part in path.split('/').filter(|s| !s.is_empty()) {
+            node = node.children.get(part)?;
+        }
+        Some(node)
+    }
+
+    fn to_json(&self, path: &str) -> String {
+        let full_path = if path.is_empty() { "/" } else { path };
+        let mut json = format!("{{\"FULL_PATH\":{}", json_str(full_path));
+        if let Some((values, access, description)) = &self.method {
+            let values: Vec<String> = values.iter().map(|x| json_num(*x)).collect();
+            json += &format!(
+                ",\"TYPE\":\"{}\",\"VALUE\":[{}],\"ACCESS\":{},\"DESCRIPTION\":{}",
+                "f".repeat(values.len()), values.join(","), access, json_str(description),
+            );
+        }
+        if !self.children.is_empty() {
+            let contents: Vec<String> = self.children.iter()
+                .map(|(k, v)| format!("{}:{}", json_str(k), v.to_json(&format!("{}/{}", path, k))))
+                .collect();
+            json += &format!(",\"CONTENTS\":{{{}}}", contents.join(","));
+        }
+        json + "}"
+    }
+
+    // a single attribute (?VALUE, ?TYPE, ...)
+    fn attribute(&self, path: &str, attr: &str) -> Option<String> {
+        let (values, access, description) = self.method.as_ref()?;
+        let value = match attr {
+            "VALUE" => format!("[{}]", values.iter().map(|x| json_num(*x)).collect::<Vec<_>>().join(",")),
+            "TYPE" => format!("\"{}\"", "f".repeat(values.len())),
+            "ACCESS" => access.to_string(),
+            "DESCRIPTION" => json_str(description),
+            "FULL_PATH" => json_str(path),
+            _ => return None,
+        };
+        Some(format!("{{\"{}\":{}}}", attr, value))
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            '\n' => out += "\\n",
+            '\t' => out += "\\t",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out + "\""
+}
+
+// json has no nan or inf
+fn json_num(x: f32) -> String {
+    if x.is_finite() { x.to_string() } else { "null".to_string() }
+}
+
+#[derive(Resource, Default)]
+pub struct OscQuery {
+    pub port: Option<u16>,
+    tree: Arc<Mutex<OscNode>>,
+    osc_port: Arc<Mutex<Option<u16>>>,
+    running: Arc<AtomicBool>,
+}
+
+impl OscQuery {
+    pub fn start(&mut self, port: u16) {
+        self.stop();
+        let Ok(listener) = TcpListener::bind(format!("0.0.0.0:{}", port)) else {
+            warn!("can't bind! another app is using port {}", port);
+            return;
+        };
+        listener.set_nonblocking(true).unwrap();
+        let running = Arc::new(AtomicBool::new(true));
+        let r = running.clone();
+        let tree = self.tree.clone();
+        let osc_port = self.osc_port.clone();
+        thread::spawn(move || {
+            while r.load(Ordering::Relaxed) {
+                if let Ok((stream, _)) = listener.accept() {
+                    respond(stream, &tree, &osc_port);
+                } else {
+                    thread::sleep(Duration::from_millis(50));
+                }
+            }
+        });
+        self.running = running;
+        self.port = Some(port);
+    }
+
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        self.port = None;
+    }
+}
+
+fn respond(mut stream: TcpStream, tree: &Mutex<OscNode>, osc_port: &Mutex<Option<u16>>) {
+    let _ = stream.set_nonblocking(false);
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(500)));
+    let mut buf = [0u8; 4096];
+    let Ok(n) = stream.read(&mut buf) else { return };
+    let request = String::from_utf8_lossy(&buf[..n]);
+    // GET /path?ATTRIBUTE HTTP/1.1
+    let mut line = request.lines().next().unwrap_or_default().split_ascii_whitespace();
+    let (Some("GET"), Some(target)) = (line.next(), line.next()) else {
+        let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+        return;
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = path.trim_end_matches('/');
+    let body = if query == "HOST_INFO" {
+        let osc_port = osc_port.lock().unwrap().map_or("null".to_string(), |p| p.to_string());
+        Some(format!(
+            "{{\"NAME\":\"quartz\",\"OSC_PORT\":{},\"OSC_TRANSPORT\":\"UDP\",\
+            \"EXTENSIONS\":{{\"ACCESS\":true,\"VALUE\":true,\"DESCRIPTION\":true}}}}",
+            osc_port,
+        ))
+    } else {
+        let tree = tree.lock().unwrap();
+        tree.get(path).and_then(|node| {
+            if query.is_empty() { Some(node.to_json(path)) } else { node.attribute(path, query) }
+        })
+    };
+    let response = match body {
+        Some(body) => format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+            Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n{}",
+            body.len(), body,
+        ),
+        None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    let _ = stream.write_all(response.as_bytes());
+}
+
+// rebuild the namespace from the osc_r circles and exposed circles (when they change)
+pub fn update_osc_query(
+    query: Res<OscQuery>,
+    receiver: Res<OscReceiver>,
+    osc_r_query: Query<(Entity, &Op, &Arr, &OpNum)>,
+    exposed_query: Query<(Entity, &Op, &Number, &Arr), With<Exposed>>,
+    changed_osc_r: Query<Ref<OpNum>, Or<(Changed<OpNum>, Changed<Op>, Changed<Arr>)>>,
+    changed_exposed: Query<(), (With<Exposed>, Or<(Changed<Exposed>, Changed<Op>, Changed<Number>, Changed<Arr>)>)>,
+    mut removed_exposed: RemovedComponents<Exposed>,
+    mut removed_ops: RemovedComponents<Op>,
+) {
+    if query.port.is_none() { return; }
+    // only when something in it changed (an osc_r circle, an exposed one, or one of those went away)
+    let removed = removed_exposed.read().count() + removed_ops.read().count() > 0;
+    let osc_r_changed = changed_osc_r.iter().any(|n| n.0 == 42 || n.is_changed());
+    if !query.is_changed() && !receiver.is_changed() && !removed
+    && !osc_r_changed && changed_exposed.is_empty() { return; }
+    let mut tree = OscNode::default();
+    for (e, op, arr, op_num) in osc_r_query.iter() {
+        if op_num.0 != 42 { continue; }
+        for address in op.0.get(5..).unwrap_or_default().split_ascii_whitespace() {
+            // patterns aren't real addresses
+            if address.contains(['*', '?', '[', '{']) { continue; }
+            tree.insert(address, arr.0.clone(), 3, format!("{} ({})", op.0, e));
+        }
+    }
+    for (e, op, num, arr) in exposed_query.iter() {
+        tree.insert(&format!("/quartz/n/{}", e), vec![num.0], 3, op.0.clone());
+        tree.insert(&format!("/quartz/arr/{}", e), arr.0.clone(), 3, op.0.clone());
+    }
+    *query.tree.lock().unwrap() = tree;
+    *query.osc_port.lock().unwrap() = receiver.udp_port();
+}
@@ -0,0 +1,390 @@
+use bevy::{
+    prelude::*,
+    scene::DynamicEntity,
+    utils::{HashMap, HashSet},
+    color::Hsla,
+};
+
+use crate::{components::*, functions::*};
+
+// the text format:
+// one line per circle:  id "op" n= o= p=x,y,z r= v= rot= c=h,s,l,a a=x,y,.. t=id,id,.. ex group collapsed
+// one line per connection:  src.lt -> dst.lt
+// only the id and op are needed, lines starting with # are comments
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn floats(v: &[f32]) -> String {
+    v.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",")
+}
+
+pub fn world_to_patch(world: &mut World) -> String {
+    let mut query = world.query_filtered::<(
+        Entity, &Op, &Number, &Order, &Transform, &Col,
+        &Vertices, &Arr, &Targets, &Holes, Has<Exposed>, Has<Group>, Has<Collapsed>,
+    ), With<Save>>();
+    let mut circles: Vec<_> = query.iter(world).collect();
+    // so saving the same scene twice gives the same file
+    circles.sort_by_key(|c| c.0);
+    let ids: HashMap<Entity, usize> = circles.iter().enumerate().map(|(i, c)| (c.0, i)).collect();
+
+    let mut text = format!(
+        "# quartz patch ({})\n# id \"op\" n= o= p=x,y,z r= v= rot= c=h,s,l,a a= t= ex group collapsed\n",
+        env!("CARGO_PKG_VERSION"),
+    );
+    for (e, op, num, order, trans, col, verts, arr, targets, _, exposed, group, collapsed) in &circles {
+        let mut line = format!("{} {}", ids[e], quote(&op.0));
+        if num.0 != 0. { line += &format!(" n={}", num.0); }
+        if order.0 != 0 { line += &format!(" o={}", order.0); }
+        line += &format!(" p={}", floats(&trans.translation.to_array()));
+        line += &format!(" r={} v={}", trans.scale.x, verts.0);
+        let rot = trans.rotation.to_euler(EulerRot::XYZ).2;
+        if rot != 0. { line += &format!(" rot={}", rot); }
+        let c = col.0;
+        line += &format!(" c={}", floats(&[c.hue, c.saturation, c.lightness, c.alpha]));
+        if !arr.0.is_empty() { line += &format!(" a={}", floats(&arr.0)); }
+        let targets: Vec<String> = targets.0.iter()
+            .filter_map(|t| ids.get(t))
+            .map(|t| t.to_string())
+            .collect();
+        if !targets.is_empty() { line += &format!(" t={}", targets.join(",")); }
+        if *exposed { line += " ex"; }
+        if *group { line += " group"; }
+        if *collapsed { line += " collapsed"; }
+        text += &line;
+        text.push('\n');
+    }
+    // connections in the order of the sink's white holes
+    for (e, .., holes, _, _, _) in &circles {
+        for hole in &holes.0 {
+            let Some(wh) = world.get::<WhiteHole>(*hole) else { continue };
+            let Some(src) = ids.get(&wh.bh_parent) else { continue };
+            text += &format!(
+                "{}.{} -> {}.{}\n",
+                src, lt_to_string(wh.link_types.0), ids[e], lt_to_string(wh.link_types.1),
+            );
+        }
+    }
+    text
+}
+
+struct PatchCircle {
+    op: String,
+    num: f32,
+    order: usize,
+    pos: Vec3,
+    radius: f32,
+    verts: usize,
+    rot: f32,
+    color: Hsla,
+    arr: Vec<f32>,
+    targets: Vec<u32>,
+    exposed: bool,
+    group: bool,
+    collapsed: bool,
+    holes: Vec<Entity>,
+}
+
+fn parse_floats(s: &str) -> Result<Vec<f32>, String> {
+    s.split(',').filter(|x| !x.is_empty())
+        .map(|x| x.parse::<f32>().map_err(|_| format!("bad number: {}", x)))
+        .collect()
+}
+
+// split the op (quoted or not) from the rest of the line
+fn split_op(s: &str) -> Result<(String, &str), String> {
+    let s = s.trim_start();
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut op = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => if let Some((_, c)) = chars.next() { op.push(c) },
+                '"' => return Ok((op, &rest[i + 1..])),
+                c => op.push(c),
+            }
+        }
+        Err(String::from("missing closing quote"))
+    } else {
+        let (op, rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        if op.is_empty() { return Err(String::from("missing op")); }
+        Ok((op.to_string(), rest))
+    }
+}
+
+fn parse_circle(line: &str, n: usize) -> Result<(u32, PatchCircle), String> {
+    let (id, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let id = id.parse::<u32>().map_err(|_| format!("bad id: {}", id))?;
+    let (op, rest) = split_op(rest)?;
+    let mut circle = PatchCircle {
+        op,
+        num: 0.,
+        order: 0,
+        pos: Vec3::new(0., 0., n as f32 * 0.01),
+        radius: 20.,
+        verts: 4,
+        rot: 0.,
+        color: Hsla::new(270., 1., 0.5, 1.),
+        arr: Vec::new(),
+        targets: Vec::new(),
+        exposed: false,
+        group: false,
+        collapsed: false,
+        holes: Vec::new(),
+    };
+    for field in rest.split_ascii_whitespace() {
+        match field {
+            "ex" => { circle.exposed = true; continue; }
+            "group" => { circle.group = true; continue; }
+            "collapsed" => { circle.collapsed = true; continue; }
+            _ => {}
+        }
+        let Some((k, v)) = field.split_once('=') else {
+            return Err(format!("unknown field: {}", field));
+        };
+        let bad = || format!("bad value: {}", field);
+        match k {
+            "n" => circle.num = v.parse().map_err(|_| bad())?,
+            "o" => circle.order = v.parse().map_err(|_| bad())?,
+            "r" => circle.radius = v.parse::<f32>().map_err(|_| bad())?.max(0.),
+            "v" => circle.verts = v.parse::<usize>().map_err(|_| bad())?.clamp(3, 64),
+            "rot" => circle.rot = v.parse().map_err(|_| bad())?,
+            "p" => {
+                let p = parse_floats(v)?;
+                match p.len() {
+                    2 => circle.pos = Vec3::new(p[0], p[1], circle.pos.z),
+                    3 => circle.pos = Vec3::new(p[0], p[1], p[2]),
+                    _ => return Err(bad()),
+                }
+            }
+            "c" => {
+                let c = parse_floats(v)?;
+                if c.len() < 3 || c.len() > 4 { return Err(bad()); }
+                circle.color = Hsla::new(c[0], c[1], c[2], c.get(3).copied().unwrap_or(1.));
+            }
+            "a" => circle.arr = parse_floats(v)?,
+            "t" => {
+                circle.targets = v.split(',').filter(|x| !x.is_empty())
+                    .map(|x| x.parse::<u32>().map_err(|_| bad()))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => return Err(format!("unknown field: {}", field)),
+        }
+    }
+    Ok((id, circle))
+}
+
+// "3.n" -> (3, -1)
+fn parse_end(s: &str) -> Result<(u32, i8), String> {
+    let s = s.trim();
+    let (id, lt) = s.split_once('.').unwrap_or((s, "0"));
+    let id = id.parse::<u32>().map_err(|_| format!("bad id: {}", id))?;
+    Ok((id, str_to_lt(lt)))
+}
+
+pub fn patch_to_scene(text: &str) -> Result<DynamicScene, String> {
+    let mut circles: HashMap<u32, PatchCircle> = HashMap::new();
+    // keep the order of the lines
+    let mut circle_ids = Vec::new();
+    let mut connections = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let err = |e: String| format!("line {}: {}", n + 1, e);
+        // (ops are quoted when saved, so they can't be mistaken for a connection)
+        if let Some((src, snk)) = line.split_once("->").filter(|_| !line.contains('"')) {
+            let src = parse_end(src).map_err(err)?;
+            let snk = parse_end(snk).map_err(err)?;
+            connections.push((n, src, snk));
+        } else {
+            let (id, circle) = parse_circle(line, circle_ids.len()).map_err(err)?;
+            if circles.insert(id, circle).is_some() {
+                return Err(err(format!("id {} is used twice", id)));
+            }
+            circle_ids.push(id);
+        }
+    }
+
+    let mut entities = Vec::new();
+    // holes get ids after the circles'
+    let mut next_id = circle_ids.iter().max().map_or(0, |x| x + 1);
+    for (n, (src, src_lt), (snk, snk_lt)) in connections {
+        if src == snk {
+            return Err(format!("line {}: can't connect {} to itself", n + 1, src));
+        }
+        let (Some(a), Some(b)) = (circles.get(&src), circles.get(&snk)) else {
+            return Err(format!("line {}: no circle with that id", n + 1));
+        };
+        // holes go on the edges facing each other
+        let dir = (b.pos.xy() - a.pos.xy()).normalize_or_zero();
+        let bh_trans = Transform {
+            translation: (a.pos.xy() + dir * a.radius)
+                .extend(a.pos.z + 0.001 * (a.holes.len() + 1) as f32),
+            scale: Vec3::new(a.radius * 0.15, a.radius * 0.15, 1.),
+            ..default()
+        };
+        let wh_trans = Transform {
+            translation: (b.pos.xy() - dir * b.radius)
+                .extend(b.pos.z + 0.001 * (b.holes.len() + 1) as f32),
+            scale: Vec3::new(b.radius * 0.15, b.radius * 0.15, 1.),
+            ..default()
+        };
+        let (bh_verts, wh_verts) = (b.verts, a.verts);
+        let bh = Entity::from_raw(next_id);
+        let wh = Entity::from_raw(next_id + 1);
+        next_id += 2;
+        entities.push(DynamicEntity {
+            entity: bh,
+            components: vec![
+                Box::new(bh_trans),
+                Box::new(Col(Hsla::new(0., 0., 0.2, 1.))),
+                Box::new(Vertices(bh_verts)),
+                Box::new(BlackHole { wh, wh_parent: Entity::from_raw(snk) }),
+                Box::new(Save),
+            ],
+        });
+        entities.push(DynamicEntity {
+            entity: wh,
+            components: vec![
+                Box::new(wh_trans),
+                Box::new(Col(Hsla::new(0., 0., 0.8, 1.))),
+                Box::new(Vertices(wh_verts)),
+                Box::new(WhiteHole {
+                    bh,
+                    bh_parent: Entity::from_raw(src),
+                    link_types: (src_lt, snk_lt),
+                    open: true,
+                }),
+                Box::new(Save),
+            ],
+        });
+        circles.get_mut(&src).unwrap().holes.push(bh);
+        circles.get_mut(&snk).unwrap().holes.push(wh);
+    }
+
+    let existing: HashSet<u32> = circle_ids.iter().copied().collect();
+    for id in circle_ids {
+        let c = circles.remove(&id).unwrap();
+        let targets = c.targets.iter()
+            .filter(|t| existing.contains(t))
+            .map(|t| Entity::from_raw(*t))
+            .collect();
+        let mut components: Vec<Box<dyn Reflect>> = vec![
+            Box::new(Transform {
+                translation: c.pos,
+                rotation: Quat::from_rotation_z(c.rot),
+                scale: Vec3::new(c.radius, c.radius, 1.),
+            }),
+            Box::new(Col(c.color)),
+            Box::new(Vertices(c.verts)),
+            Box::new(Op(c.op)),
+            Box::new(Number(c.num)),
+            Box::new(Arr(c.arr)),
+            Box::new(Order(c.order)),
+            Box::new(Targets(targets)),
+            Box::new(Holes(c.holes)),
+            Box::new(Save),
+        ];
+        if c.exposed { components.push(Box::new(Exposed)); }
+        if c.group { components.push(Box::new(Group)); }
+        if c.collapsed { components.push(Box::new(Collapsed)); }
+        entities.push(DynamicEntity { entity: Entity::from_raw(id), components });
+    }
+    Ok(DynamicScene { resources: Vec::new(), entities })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circle(world: &mut World, op: &str, x: f32) -> Entity {
+        world.spawn((
+            Op(op.to_string()),
+            Number(0.),
+            Order(1),
+            Transform::from_xyz(x, 0., 0.).with_scale(Vec3::new(20., 20., 1.)),
+            Col(Hsla::new(270., 1., 0.5, 1.)),
+            Vertices(4),
+            Arr(Vec::new()),
+            Targets(Vec::new()),
+            Holes(Vec::new()),
+            Save,
+        )).id()
+    }
+
+    fn get<T: Reflect>(entity: &DynamicEntity) -> Option<&T> {
+        entity.components.iter().find_map(|c| (**c).downcast_ref::<T>())
+    }
+
+    #[test]
+    fn world_to_patch_and_back() {
+        let mut world = World::new();
+        let a = circle(&mut world, "sum", 0.);
+        let b = circle(&mut world, "out()", 100.);
+        world.entity_mut(a).insert((Number(3.), Arr(vec![1., 2.5]), Targets(vec![b]), Exposed));
+        world.entity_mut(b).insert(Order(2));
+        let bh = world.spawn(BlackHole { wh: Entity::PLACEHOLDER, wh_parent: b }).id();
+        let wh = world.spawn(WhiteHole { bh, bh_parent: a, link_types: (-1, 1), open: false }).id();
+        world.get_mut::<BlackHole>(bh).unwrap().wh = wh;
+        world.get_mut::<Holes>(a).unwrap().0.push(bh);
+        world.get_mut::<Holes>(b).unwrap().0.push(wh);
+
+        let text = world_to_patch(&mut world);
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(lines, [
+            "0 \"sum\" n=3 o=1 p=0,0,0 r=20 v=4 c=270,1,0.5,1 a=1,2.5 t=1 ex",
+            "1 \"out()\" o=2 p=100,0,0 r=20 v=4 c=270,1,0.5,1",
+            "0.n -> 1.1",
+        ]);
+
+        let scene = patch_to_scene(&text).unwrap();
+        // two circles and the two holes between them
+        assert_eq!(scene.entities.len(), 4);
+        let sum = scene.entities.iter().find(|e| get::<Op>(e).is_some_and(|op| op.0 == "sum")).unwrap();
+        assert_eq!(sum.entity, Entity::from_raw(0));
+        assert_eq!(get::<Number>(sum).unwrap().0, 3.);
+        assert_eq!(get::<Arr>(sum).unwrap().0, vec![1., 2.5]);
+        assert_eq!(get::<Targets>(sum).unwrap().0, vec![Entity::from_raw(1)]);
+        assert!(get::<Exposed>(sum).is_some());
+        let wh = scene.entities.iter().find_map(get::<WhiteHole>).unwrap();
+        assert_eq!(wh.bh_parent, Entity::from_raw(0));
+        assert_eq!(wh.link_types, (-1, 1));
+        let holes = &get::<Holes>(sum).unwrap().0;
+        assert_eq!(holes.len(), 1);
+        assert_eq!(holes[0], wh.bh);
+    }
+
+    #[test]
+    fn parse_circle_defaults_and_quotes() {
+        let (id, c) = parse_circle("7 \"osc_r /a \\\"b\\\"\" p=1,2", 0).unwrap();
+        assert_eq!(id, 7);
+        assert_eq!(c.op, "osc_r /a \"b\"");
+        assert_eq!(c.pos, Vec3::new(1., 2., 0.));
+        assert_eq!((c.radius, c.verts, c.order), (20., 4, 0));
+    }
+
+    #[test]
+    fn parse_circle_errors() {
+        let err = |line: &str| parse_circle(line, 0).err();
+        assert_eq!(err("x sum"), Some("bad id: x".to_string()));
+        assert_eq!(err("0"), Some("missing op".to_string()));
+        assert_eq!(err("0 \"sum"), Some("missing closing quote".to_string()));
+        assert_eq!(err("0 sum q=1"), Some("unknown field: q=1".to_string()));
+        assert_eq!(err("0 sum big"), Some("unknown field: big".to_string()));
+        assert_eq!(err("0 sum n=one"), Some("bad value: n=one".to_string()));
+        assert_eq!(err("0 sum p=1"), Some("bad value: p=1".to_string()));
+        assert_eq!(err("0 sum c=1,2"), Some("bad value: c=1,2".to_string()));
+        assert_eq!(err("0 sum a=1,x"), Some("bad number: x".to_string()));
+    }
+
+    #[test]
+    fn patch_errors_have_line_numbers() {
+        let err = |text: &str| patch_to_scene(text).err();
+        assert_eq!(err("0 sum\n0 sum"), Some("line 2: id 0 is used twice".to_string()));
+        assert_eq!(err("# hi\n0 sum\n0.n -> 0.1"), Some("line 3: can't connect 0 to itself".to_string()));
+        assert_eq!(err("0 sum\n0.n -> 5.1"), Some("line 2: no circle with that id".to_string()));
+        assert_eq!(err("0 sum\nx.n -> 0.1"), Some("line 2: bad id: x".to_string()));
+    }
+}
@@ -11,6 +11,8 @@ use bevy::{
         RenderLayers,
     },
     input::keyboard::{KeyboardInput, Key},
+    utils::{HashMap, HashSet},
+    tasks::IoTaskPool,
     prelude::*
 };
 
@@ -23,6 +25,7 @@ use crate::{
     nodes::*,
     functions::*,
     osc::*,
+    midi::*,
 };
 
 pub fn sort_by_order(
@@ -43,22 +46,277 @@ pub fn sort_by_order(
     }
 }
 
+// how deep process circles can be nested
+const PROCESS_DEPTH: usize = 8;
+// the most circles processed through process circles in a frame
+const LOOP_QUEUE_LIMIT: usize = 10000;
+
+// add the targets of a process circle (and of the process circles in them) to the loop queue
+fn expand_process(
+    id: Entity,
+    depth: usize,
+    loopq: &mut Vec<(Entity, usize)>,
+    op_query: &Query<&Op>,
+    targets_query: &Query<&Targets>,
+    holes_query: &Query<&Holes>,
+    wh_query: &Query<&WhiteHole>,
+    num_query: &Query<&Number>,
+) {
+    let Some(mut n) = process_iterations(&op_query.get(id).unwrap().0) else { return };
+    // n -> 2 overrides the iterations
+    for hole in &holes_query.get(id).unwrap().0 {
+        if let Ok(wh) = wh_query.get(*hole) {
+            if wh.link_types == (-1, 2) {
+                n = num_query.get(wh.bh_parent).unwrap().0.max(0.) as usize;
+            }
+        }
+    }
+    // more than that can't fit in the queue anyway
+    let n = n.min(LOOP_QUEUE_LIMIT);
+    let gate = loopq.len();
+    loopq.push((id, 0));
+    'outer: for _ in 0..n {
+        let before = loopq.len();
+        for t in &targets_query.get(id).unwrap().0 {
+            if loopq.len() >= LOOP_QUEUE_LIMIT { break 'outer; }
+            // only add existing circles (that aren't holes)
+            let Ok(op) = op_query.get(*t) else { continue };
+            if process_iterations(&op.0).is_some() {
+                if depth + 1 < PROCESS_DEPTH {
+                    expand_process(*t, depth + 1, loopq, op_query, targets_query, holes_query, wh_query, num_query);
+                }
+            } else {
+                loopq.push((*t, 0));
+            }
+        }
+        // no targets (or only ones too deep), the rest of the iterations won't add any either
+        if loopq.len() == before { break; }
+    }
+    let len = loopq.len() - gate - 1;
+    if len == 0 {
+        loopq.pop();
+    } else {
+        loopq[gate].1 = len;
+    }
+}
+
 pub fn prepare_loop_queue(
     mut loopq: ResMut<LoopQueue>,
     queue: Res<Queue>,
     op_query: Query<&Op>,
     targets_query: Query<&Targets>,
+    holes_query: Query<&Holes>,
+    wh_query: Query<&WhiteHole>,
+    num_query: Query<&Number>,
+    status: Res<StatusChannel>,
+    mut truncated: Local<bool>,
 ) {
     loopq.0.clear();
-    for id in queue.0.iter().flatten() {
-        if op_query.get(*id).unwrap().0 == "process" {
-            let targets = &targets_query.get(*id).unwrap().0;
-            for t in targets {
-                // only add existing circles (that aren't holes)
-                if op_query.contains(*t) { loopq.0.push(*t); }
+    let is_process = |e: &Entity| op_query.get(*e).is_ok_and(|op| process_iterations(&op.0).is_some());
+    // process circles targeted by other ones run through those, not on their own too
+    let mut nested = HashSet::new();
+    for id in queue.0.iter().flatten().filter(|e| is_process(*e)) {
+        if let Ok(targets) = targets_query.get(*id) {
+            nested.extend(targets.0.iter().filter(|t| *t != id && is_process(*t)));
+        }
+    }
+    for id in queue.0.iter().flatten().filter(|e| !nested.contains(*e)) {
+        expand_process(*id, 0, &mut loopq.0, &op_query, &targets_query, &holes_query, &wh_query, &num_query);
+    }
+    // unless they're only inside each other (a loop of process circles)
+    let mut reached: HashSet<Entity> = loopq.0.iter().map(|x| x.0).collect();
+    for id in queue.0.iter().flatten().filter(|e| nested.contains(*e)) {
+        if reached.contains(id) { continue; }
+        let start = loopq.0.len();
+        expand_process(*id, 0, &mut loopq.0, &op_query, &targets_query, &holes_query, &wh_query, &num_query);
+        reached.extend(loopq.0[start..].iter().map(|x| x.0));
+    }
+    // say it once when it starts happening, not every frame
+    let full = loopq.0.len() >= LOOP_QUEUE_LIMIT;
+    if full && !*truncated {
+        let msg = format!("process circles want more than {} runs per frame, the rest are skipped", LOOP_QUEUE_LIMIT);
+        let _ = status.0.0.send(msg);
+    }
+    *truncated = full;
+}
+
+// connections between processed circles (source, sink)
+// circles writing into their targets (process, distro, ..) count as sources for them
+fn order_edges(
+    orders: &HashMap<Entity, usize>,
+    holes_query: &Query<(Entity, &Holes)>,
+    wh_query: &Query<&WhiteHole>,
+    targets_query: &Query<(Entity, &Targets)>,
+) -> Vec<(Entity, Entity)> {
+    let mut edges = Vec::new();
+    for (snk, holes) in holes_query.iter() {
+        if orders.get(&snk).map_or(true, |o| *o == 0) { continue; }
+        for hole in &holes.0 {
+            if let Ok(wh) = wh_query.get(*hole) {
+                if orders.get(&wh.bh_parent).is_some_and(|o| *o > 0) {
+                    edges.push((wh.bh_parent, snk));
+                }
+            }
+        }
+    }
+    for (src, targets) in targets_query.iter() {
+        if orders.get(&src).map_or(true, |o| *o == 0) { continue; }
+        for t in &targets.0 {
+            if *t != src && orders.get(t).is_some_and(|o| *o > 0) {
+                edges.push((src, *t));
+            }
+        }
+    }
+    edges
+}
+
+// strongly connected components (tarjan's), sources first
+// a component with more than one circle is a feedback loop
+fn order_components(nodes: &[Entity], edges: &[(Entity, Entity)]) -> Vec<Vec<Entity>> {
+    let mut outs: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    for (src, snk) in edges {
+        outs.entry(*src).or_default().push(*snk);
+    }
+    let mut index: HashMap<Entity, usize> = HashMap::new();
+    let mut low: HashMap<Entity, usize> = HashMap::new();
+    let mut stack = Vec::new();
+    let mut on_stack = HashSet::new();
+    let mut components = Vec::new();
+    for root in nodes {
+        if index.contains_key(root) { continue; }
+        // (circle, next output to visit)
+        let mut work = vec![(*root, 0)];
+        while let Some((v, i)) = work.pop() {
+            if i == 0 {
+                index.insert(v, index.len());
+                low.insert(v, index[&v]);
+                stack.push(v);
+                on_stack.insert(v);
+            }
+            let succ = outs.get(&v).map_or(&[][..], |x| x.as_slice());
+            if let Some(w) = succ.get(i) {
+                work.push((v, i + 1));
+                if !index.contains_key(w) {
+                    work.push((*w, 0));
+                } else if on_stack.contains(w) {
+                    let l = low[&v].min(index[w]);
+                    low.insert(v, l);
+                }
+                continue;
+            }
+            if low[&v] == index[&v] {
+                let mut component = Vec::new();
+                while let Some(w) = stack.pop() {
+                    on_stack.remove(&w);
+                    component.push(w);
+                    if w == v { break; }
+                }
+                components.push(component);
+            }
+            if let Some((parent, _)) = work.last() {
+                let l = low[parent].min(low[&v]);
+                low.insert(*parent, l);
             }
         }
     }
+    // they come out sinks first
+    components.reverse();
+    components
+}
+
+// flag circles reading from circles with an equal or higher order
+pub fn check_order(
+    order_query: Query<(Entity, &Order)>,
+    holes_query: Query<(Entity, &Holes)>,
+    wh_query: Query<&WhiteHole>,
+    targets_query: Query<(Entity, &Targets)>,
+    mut late: ResMut<LateCircles>,
+    status: Res<StatusChannel>,
+    mut last_loops: Local<Vec<String>>,
+    mut order_change: EventReader<OrderChange>,
+    // connections and targets change the edges without changing any order
+    changed_edges: Query<(), Or<(Changed<Holes>, Changed<Targets>)>>,
+    mut removed_wh: RemovedComponents<WhiteHole>,
+    mut removed_targets: RemovedComponents<Targets>,
+) {
+    let removed = removed_wh.read().count() + removed_targets.read().count() > 0;
+    if order_change.read().count() == 0 && changed_edges.is_empty() && !removed { return; }
+    let orders: HashMap<Entity, usize> = order_query.iter().map(|(e, o)| (e, o.0)).collect();
+    let edges = order_edges(&orders, &holes_query, &wh_query, &targets_query);
+    late.0.clear();
+    for (src, snk) in &edges {
+        if orders[src] >= orders[snk] {
+            late.0.insert(*snk);
+        }
+    }
+    // say which feedback loops there are (when that changes)
+    let mut nodes: Vec<Entity> = orders.iter().filter(|(_, o)| **o > 0).map(|(e, _)| *e).collect();
+    nodes.sort();
+    let mut loops: Vec<String> = order_components(&nodes, &edges).into_iter()
+        .filter(|c| c.len() > 1)
+        .map(|mut c| {
+            c.sort();
+            c.iter().map(|e| e.to_string()).collect::<Vec<String>>().join(" ")
+        })
+        .collect();
+    loops.sort();
+    if !loops.is_empty() && loops != *last_loops {
+        let _ = status.0.0.send(format!("{} feedback loops: [{}]", loops.len(), loops.join("] [")));
+    }
+    *last_loops = loops;
+}
+
+// give every processed circle an order higher than the circles it reads from
+// (circles in a feedback loop keep their relative order)
+pub fn auto_order(
+    mut order_query: Query<(Entity, &mut Order), Without<Internal>>,
+    holes_query: Query<(Entity, &Holes)>,
+    wh_query: Query<&WhiteHole>,
+    targets_query: Query<(Entity, &Targets)>,
+    mut order_change: EventWriter<OrderChange>,
+    status: Res<StatusChannel>,
+) {
+    let orders: HashMap<Entity, usize> = order_query.iter().map(|(e, o)| (e, o.0)).collect();
+    let edges = order_edges(&orders, &holes_query, &wh_query, &targets_query);
+    let mut nodes: Vec<Entity> = orders.iter().filter(|(_, o)| **o > 0).map(|(e, _)| *e).collect();
+    nodes.sort();
+    let mut ins: HashMap<Entity, Vec<Entity>> = HashMap::new();
+    for (src, snk) in &edges {
+        ins.entry(*snk).or_default().push(*src);
+    }
+    let mut new_orders: HashMap<Entity, usize> = HashMap::new();
+    let mut loops = Vec::new();
+    for mut component in order_components(&nodes, &edges) {
+        let mut base = 1;
+        for e in &component {
+            for src in ins.get(e).into_iter().flatten() {
+                if let Some(o) = new_orders.get(src) { base = base.max(o + 1); }
+            }
+        }
+        component.sort_by_key(|e| (orders[e], *e));
+        for (i, e) in component.iter().enumerate() {
+            new_orders.insert(*e, base + i);
+        }
+        if component.len() > 1 {
+            let ids: Vec<String> = component.iter().map(|e| e.to_string()).collect();
+            loops.push(ids.join(" "));
+        }
+    }
+    let mut changed = 0;
+    for (e, mut order) in order_query.iter_mut() {
+        if let Some(o) = new_orders.get(&e) {
+            if order.0 != *o {
+                order.0 = *o;
+                changed += 1;
+            }
+        }
+    }
+    if changed > 0 { order_change.send_default(); }
+    let mut msg = format!("reordered {} circles", changed);
+    if !loops.is_empty() {
+        msg += &format!(", {} feedback loops: [{}]", loops.len(), loops.join("] ["));
+    }
+    let _ = status.0.0.send(msg);
 }
 
 #[derive(SystemParam)]
@@ -103,12 +361,21 @@ pub struct Access<'w, 's> {
     text_size: ResMut<'w, TextSize>,
     osc_sender: ResMut<'w, OscSender>,
     osc_receiver: ResMut<'w, OscReceiver>,
-    osc_messages: Local<'s, Vec<rosc::OscMessage>>,
     node_limit: Res<'w, NodeLimit>,
-    input_receivers: Res<'w, InputReceivers>,
+    input_buffer: Res<'w, InputBufferRes>,
     op_num_query: Query<'w, 's, &'static OpNum>,
     key_event: EventReader<'w, 's, KeyboardInput>,
     ortho: Query<'w, 's, &'static mut OrthographicProjection>,
+    out_nets: ResMut<'w, OutNets>,
+    dac_change: EventWriter<'w, DacChange>,
+    record_event: EventWriter<'w, RecordCommand>,
+    sample_rate: Res<'w, SampleRate>,
+    wave_chan: Res<'w, WaveChannel>,
+    status_chan: Res<'w, StatusChannel>,
+    midi_in: Res<'w, MidiInChannel>,
+    midi_out: Res<'w, MidiOutChannel>,
+    ports_query: Query<'w, 's, &'static Ports>,
+    internal_query: Query<'w, 's, &'static Internal>,
 }
 
 pub fn process(
@@ -123,11 +390,31 @@ pub fn process(
     camera_query: Query<(Entity, &Camera, &GlobalTransform)>,
     windows: Query<(Entity, &Window)>,
     mut commands: Commands,
-    mut slot: ResMut<SlotRes>,
 ) {
     let key_event = access.key_event.read().collect::<Vec<_>>();
-    for id in queue.0.iter().flatten().chain(loopq.0.iter()) {
+    let midi_messages = access.midi_in.0.1.try_iter().collect::<Vec<_>>();
+    let osc_messages = access.osc_receiver.messages.try_iter().collect::<Vec<_>>();
+    let mut waves = access.wave_chan.0.1.try_iter().map(|(e, lt, w)| (e, (lt, w))).collect::<HashMap<_, _>>();
+    let mut skip = 0;
+    for (id, gate) in queue.0.iter().flatten().map(|id| (*id, 0)).chain(loopq.0.iter().copied()) {
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+        let id = &id;
         let holes = &holes_query.get(*id).unwrap().0;
+        if gate > 0 {
+            // a process circle, skip its targets if its n -> 1 input is zero
+            for hole in holes {
+                if let Ok(wh) = white_hole_query.get(*hole) {
+                    if wh.link_types == (-1, 1) && access.num_query.get(wh.bh_parent).unwrap().0 == 0. {
+                        skip = gate;
+                    }
+                }
+            }
+            continue;
+        }
+        let mut inlets_to_open = Vec::new();
         for hole in holes {
             let mut lt_to_open = 0;
             if let Ok(wh) = white_hole_query.get(*hole) {
@@ -148,6 +435,14 @@ pub fn process(
                     -12 => {
                         input = access.trans_query.get(wh.bh_parent).unwrap().rotation.to_euler(EulerRot::XYZ).2;
                     }
+                    // outlet k of a patch circle
+                    k if k > 0 => {
+                        if let Ok(ports) = access.ports_query.get(wh.bh_parent) {
+                            if let Some((_, outlet)) = ports.outlets.iter().find(|x| x.0 == k) {
+                                input = access.num_query.get(*outlet).unwrap().0;
+                            }
+                        }
+                    }
                     _ => {}
                 }
                 match wh.link_types.1 {
@@ -168,6 +463,15 @@ pub fn process(
                         let q = Quat::from_euler(EulerRot::XYZ, 0., 0., input);
                         access.trans_query.get_mut(*id).unwrap().rotation = q;
                     }
+                    // inlet k of a patch circle (audio is handled by the patch op)
+                    k if k > 0 && wh.link_types.0 != 0 => {
+                        if let Ok(ports) = access.ports_query.get(*id) {
+                            for (_, inlet) in ports.inlets.iter().filter(|x| x.0 == k) {
+                                access.num_query.get_mut(*inlet).unwrap().0 = input;
+                                inlets_to_open.push(*inlet);
+                            }
+                        }
+                    }
                     _ => {}
                 }
                 if wh.link_types == (-13, -13) {
@@ -193,6 +497,16 @@ pub fn process(
                 }
             }
         }
+        // inlets pass the number on to whatever reads it inside the subpatch
+        for inlet in inlets_to_open {
+            for hole in &holes_query.get(inlet).unwrap().0 {
+                if let Ok(bh) = black_hole_query.get(*hole) {
+                    if let Ok(mut wh) = white_hole_query.get_mut(bh.wh) {
+                        if wh.link_types.0 == -1 { wh.open = true; }
+                    }
+                }
+            }
+        }
         let mut lt_to_open = None;
         let op = access.op_query.get(*id).unwrap().0.as_str();
         let op_num = access.op_num_query.get(*id).unwrap().0;
@@ -862,7 +1176,8 @@ pub fn process(
                 for hole in holes {
                     if let Ok(wh) = white_hole_query.get(*hole) {
                         if wh.link_types == (-1, 1) && access.num_query.get(wh.bh_parent).unwrap().0 != 0. {
-                            let win = windows.single().0;
+                            // no window in headless mode
+                            let Ok((win, _)) = windows.get_single() else { continue };
                             let epoch = std::time::UNIX_EPOCH;
                             let now = std::time::SystemTime::now();
                             if let Ok(dur) = now.duration_since(epoch) {
@@ -891,24 +1206,38 @@ pub fn process(
                         }
                     }
                 }
-                access.osc_messages.clear();
-                if let Some(socket) = &access.osc_receiver.socket {
-                    for _ in 0..10 {
-                        if let Some(packet) = receive_packet(socket) {
-                            let mut buffer = Vec::new();
-                            unpacket(packet, &mut buffer);
-                            access.osc_messages.append(&mut buffer);
-                        }
-                    }
-                }
             }
             42 => { // osc_r
-                for message in &access.osc_messages {
-                    if op.contains(&message.addr) {
+                for (message, time) in &osc_messages {
+                    if osc_r_match(op.get(5..).unwrap_or_default(), &message.addr) {
                         let arr = &mut access.arr_query.get_mut(*id).unwrap().0;
                         arr.clear();
-                        for arg in message.args.clone() {
-                            if let rosc::OscType::Float(f) = arg { arr.push(f); }
+                        let mut strings = Vec::new();
+                        osc_args(&message.args, arr, &mut strings);
+                        // string args set the ops of the targets (in order)
+                        let targets = &access.targets_query.get(*id).unwrap().0;
+                        for (t, s) in targets.iter().zip(strings) {
+                            if let Ok(target_op) = access.op_query.get(*t) {
+                                if target_op.0 != s {
+                                    commands.entity(*t).insert((
+                                        OpNum(str_to_op_num(&s)),
+                                        Network(str_to_net(&s)),
+                                        OpChanged(true),
+                                        Op(s),
+                                    ));
+                                }
+                            }
+                        }
+                        // time tag of the bundle (seconds from now)
+                        if let Some(time) = time {
+                            access.num_query.get_mut(*id).unwrap().0 = time_from_now(*time);
+                            for hole in holes {
+                                if let Ok(bh) = black_hole_query.get(*hole) {
+                                    if let Ok(mut wh) = white_hole_query.get_mut(bh.wh) {
+                                        if wh.link_types.0 == -1 { wh.open = true; }
+                                    }
+                                }
+                            }
                         }
                         lt_to_open = Some(-13);
                     }
@@ -918,9 +1247,11 @@ pub fn process(
                 for hole in holes {
                     if let Ok(wh) = white_hole_query.get(*hole) {
                         if wh.link_types == (-13, 1) && wh.open {
-                            if let Some(address) = op.get(6..) {
+                            // osc_s /address [endpoint]
+                            let mut args = op.get(6..).unwrap_or_default().split_ascii_whitespace();
+                            if let Some(address) = args.next() {
                                 let arr = access.arr_query.get(wh.bh_parent).unwrap().0.clone();
-                                access.osc_sender.send(address, arr);
+                                access.osc_sender.send_to(args.next(), address, arr);
                             }
                         }
                     }
@@ -929,7 +1260,8 @@ pub fn process(
             // -------------------- input --------------------
             44 => { // mouse
                 let (_, cam, cam_transform) = camera_query.single();
-                if let Some(cursor_pos) = windows.single().1.cursor_position() {
+                let cursor_pos = windows.get_single().ok().and_then(|w| w.1.cursor_position());
+                if let Some(cursor_pos) = cursor_pos {
                     if let Some(point) = cam.viewport_to_world_2d(cam_transform, cursor_pos) {
                         access.arr_query.get_mut(*id).unwrap().0 = point.to_array().into();
                         lt_to_open = Some(-13);
@@ -1063,6 +1395,97 @@ pub fn process(
                     }
                 }
             }
+            94 => { // midi_cc
+                let mut args = op.split_ascii_whitespace().skip(1);
+                let ch = args.next().and_then(|s| s.parse::<u8>().ok()).unwrap_or(0);
+                let cc = args.next().and_then(|s| s.parse::<u8>().ok());
+                for msg in &midi_messages {
+                    if msg.len() == 3 && msg[0] & 0xF0 == 0xB0 && midi_channel(msg[0], ch)
+                    && Some(msg[1]) == cc {
+                        access.num_query.get_mut(*id).unwrap().0 = msg[2] as f32;
+                        lt_to_open = Some(-1);
+                    }
+                }
+            }
+            95 | 96 => { // midi_note | midi_notes
+                let ch = op.split_ascii_whitespace().nth(1).and_then(|s| s.parse::<u8>().ok()).unwrap_or(0);
+                for msg in &midi_messages {
+                    if msg.len() != 3 || !midi_channel(msg[0], ch) { continue; }
+                    let status = msg[0] & 0xF0;
+                    // note on with zero velocity is a note off
+                    let vel = if status == 0x90 { msg[2] } else if status == 0x80 { 0 } else { continue };
+                    let note = msg[1] as f32;
+                    let arr = &mut access.arr_query.get_mut(*id).unwrap().0;
+                    if op_num == 95 {
+                        *arr = vec![note, vel as f32];
+                    } else if vel > 0 {
+                        if !arr.contains(&note) { arr.push(note); }
+                    } else {
+                        arr.retain(|&x| x != note);
+                    }
+                    lt_to_open = Some(-13);
+                }
+            }
+            97 => { // midi_clock
+                for msg in &midi_messages {
+                    match msg.first() {
+                        // clock tick (24 per quarter note)
+                        Some(0xF8) => {
+                            access.num_query.get_mut(*id).unwrap().0 += 1.;
+                            lt_to_open = Some(-1);
+                        }
+                        // start
+                        Some(0xFA) => {
+                            access.num_query.get_mut(*id).unwrap().0 = 0.;
+                            lt_to_open = Some(-1);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            98 => { // midi_s_note
+                let ch = op.split_ascii_whitespace().nth(1).and_then(|s| s.parse::<u8>().ok()).unwrap_or(1);
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        if wh.link_types == (-13, 1) && wh.open {
+                            let arr = &access.arr_query.get(wh.bh_parent).unwrap().0;
+                            if let (Some(note), Some(vel)) = (arr.first(), arr.get(1)) {
+                                let note = note.clamp(0., 127.) as u8;
+                                let vel = vel.clamp(0., 127.) as u8;
+                                let kind = if vel > 0 { 0x90 } else { 0x80 };
+                                let msg = vec![midi_status(kind, ch), note, vel];
+                                let _ = access.midi_out.0.0.send(msg);
+                            }
+                        }
+                    }
+                }
+            }
+            99 => { // midi_s_cc
+                let mut args = op.split_ascii_whitespace().skip(1);
+                let ch = args.next().and_then(|s| s.parse::<u8>().ok()).unwrap_or(1);
+                let cc = args.next().and_then(|s| s.parse::<u8>().ok()).unwrap_or(0).min(127);
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        if wh.link_types == (-1, 1) && wh.open {
+                            let val = access.num_query.get(wh.bh_parent).unwrap().0.clamp(0., 127.) as u8;
+                            let _ = access.midi_out.0.0.send(vec![midi_status(0xB0, ch), cc, val]);
+                        }
+                    }
+                }
+            }
+            100 => { // midi_s_clock
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        let n = access.num_query.get(wh.bh_parent).unwrap().0;
+                        if wh.link_types == (-1, 1) && wh.open && n != 0. {
+                            let _ = access.midi_out.0.0.send(vec![0xF8]);
+                        } else if wh.link_types == (-1, 2) && wh.open {
+                            let msg = if n != 0. { 0xFA } else { 0xFC };
+                            let _ = access.midi_out.0.0.send(vec![msg]);
+                        }
+                    }
+                }
+            }
             // -------------------- data management --------------------
             // uses the array to store previous num value
             52 | 53 => { // rise | fall
@@ -1108,7 +1531,7 @@ pub fn process(
                     }
                 }
             }
-            55 => { // num_push
+            55 => { // push_num
                 for hole in holes {
                     if let Ok(wh) = white_hole_query.get(*hole) {
                         if wh.link_types == (-1, 1) && wh.open
@@ -1192,11 +1615,13 @@ pub fn process(
                             access.net_query.get_mut(*id).unwrap().0 = input_net.clone();
                         }
                         if wh.link_types == (-1, 2) && access.num_query.get(wh.bh_parent).unwrap().0 != 0. {
-                            let len = access.num_query.get(*id).unwrap().0 / 44100.;
+                            let sr = access.sample_rate.0;
+                            let len = access.num_query.get(*id).unwrap().0 as f64 / sr;
                             let output = &mut access.arr_query.get_mut(*id).unwrap().0;
                             let net = &mut access.net_query.get_mut(*id).unwrap().0;
                             if net.inputs() == 0 && net.outputs() > 0 && len >= 0. {
-                                let wave = Wave::render(44100., len.into(), net);
+                                net.set_sample_rate(sr);
+                                let wave = Wave::render(sr, len, net);
                                 *output = wave.channel(0).clone();
                             }
                             lt_to_open = Some(-13);
@@ -1204,6 +1629,63 @@ pub fn process(
                     }
                 }
             }
+            93 => { // load_wave
+                // a file that finished loading in the background
+                if let Some((lt, result)) = waves.remove(id) {
+                    match result {
+                        Ok((file_sr, wave)) => {
+                            access.num_query.get_mut(*id).unwrap().0 = file_sr;
+                            if lt == 1 {
+                                // one output per channel
+                                let wave = std::sync::Arc::new(wave);
+                                let mut net = Net::new(0, 0);
+                                for chan in 0..wave.channels() {
+                                    net = net | Net::wrap(Box::new(wavech(&wave, chan, Some(0))));
+                                }
+                                access.net_query.get_mut(*id).unwrap().0 = net;
+                                lt_to_open = Some(0);
+                            } else {
+                                // interleaved
+                                let output = &mut access.arr_query.get_mut(*id).unwrap().0;
+                                output.clear();
+                                for i in 0..wave.len() {
+                                    for chan in 0..wave.channels() {
+                                        output.push(wave.at(chan, i));
+                                    }
+                                }
+                                lt_to_open = Some(-13);
+                            }
+                        }
+                        Err(e) => {
+                            let _ = access.status_chan.0.0.send(e);
+                        }
+                    }
+                }
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        if wh.open
+                        && (wh.link_types == (-1, 1) || wh.link_types == (-1, 2))
+                        && access.num_query.get(wh.bh_parent).unwrap().0 != 0. {
+                            let file = op.get(10..).unwrap_or_default().to_string();
+                            let sender = access.wave_chan.0.0.clone();
+                            let (id, lt, sr) = (*id, wh.link_types.1, access.sample_rate.0);
+                            // decoding can take a while, don't hold the frame
+                            IoTaskPool::get().spawn(async move {
+                                let result = match Wave::load(format!("assets/{}", file)) {
+                                    Ok(wave) => {
+                                        let file_sr = wave.sample_rate() as f32;
+                                        // the node plays at the output's rate, the array is left as is
+                                        let wave = if lt == 1 { resample_wave(&wave, sr) } else { wave };
+                                        Ok((file_sr, wave))
+                                    }
+                                    Err(e) => Err(format!("couldn't load {}: {}", file, e)),
+                                };
+                                let _ = sender.send((id, lt, result));
+                            }).detach();
+                        }
+                    }
+                }
+            }
             // -------------------- audio nodes --------------------
             61 => { // var()
                 if access.op_changed_query.get(*id).unwrap().0 {
@@ -1221,12 +1703,45 @@ pub fn process(
                     }
                 //}
             }
-            62 => { // in() | adc()
+            101 => { // osc_in()
                 if access.op_changed_query.get(*id).unwrap().0 {
-                    let net = &mut access.net_query.get_mut(*id).unwrap().0;
-                    let lr = access.input_receivers.0.clone();
-                    let rr = access.input_receivers.1.clone();
-                    *net = Net::wrap(Box::new(An(InputNode::new(lr, rr))));
+                    // osc_in(/address, outputs)
+                    let args = op.get(7..).unwrap_or_default().trim_end_matches(')');
+                    let mut args = args.split(',').map(|s| s.trim());
+                    let address = args.next().unwrap_or_default();
+                    let n = args.next().and_then(|s| s.parse::<usize>().ok()).unwrap_or(1).clamp(1, 64);
+                    let mut net = Net::new(0, 0);
+                    for s in access.osc_receiver.shared(address, n) {
+                        net = net | Net::wrap(Box::new(var(&s) >> follow(0.01)));
+                    }
+                    access.net_query.get_mut(*id).unwrap().0 = net;
+                    lt_to_open = Some(0);
+                }
+            }
+            62 => { // in() | adc()
+                // rebuild when the input device changes too
+                if access.op_changed_query.get(*id).unwrap().0 || access.input_buffer.is_changed() {
+                    // in() has all channels, in(n) is just channel n
+                    let channel = op.split('(').nth(1)
+                        .and_then(|s| s.trim_end_matches(')').trim().parse::<usize>().ok());
+                    let buffer = &access.input_buffer.0;
+                    let mut input = Net::new(0, 0);
+                    for i in 0..buffer.channels() {
+                        if channel.is_none() || channel == Some(i) {
+                            let node = InputNode::new(buffer.clone(), i, access.sample_rate.0);
+                            input = input | Net::wrap(Box::new(An(node)));
+                        }
+                    }
+                    // silence if there's no input device or no such channel
+                    // (in() is stereo then, like it was before multichannel input)
+                    if input.outputs() == 0 {
+                        input = if channel.is_some() {
+                            Net::wrap(Box::new(dc(0.)))
+                        } else {
+                            Net::wrap(Box::new(dc((0., 0.))))
+                        };
+                    }
+                    access.net_query.get_mut(*id).unwrap().0 = input;
                     lt_to_open = Some(0);
                 }
             }
@@ -1375,14 +1890,17 @@ pub fn process(
                     if let Some(input) = input {
                         let net = access.net_query.get(input).unwrap().0.clone();
                         let n = access.num_query.get(*id).unwrap().0;
+                        let sr = access.sample_rate.0;
                         let output = &mut access.net_query.get_mut(*id).unwrap().0;
                         if op_num == 68 && net.inputs() == 0 && net.outputs() == 1 { // kr()
                             *output = Net::wrap(Box::new(An(Kr::new(net, n.max(1.) as usize))));
+                            output.set_sample_rate(sr);
                         } else if op_num == 69 && net.inputs() == 0 && net.outputs() == 1 { // reset()
-                            *output = Net::wrap(Box::new(An(Reset::new(net, n))));
+                            *output = Net::wrap(Box::new(An(Reset::new(net, n, sr as f32))));
                         } else if op_num == 70 { // sr()
-                            *output = net;
-                            output.set_sample_rate(n as f64);
+                            // defaults to the device rate
+                            let n = if n > 0. { n as f64 } else { sr };
+                            *output = Net::wrap(Box::new(FixedSr::new(net, n)));
                         }
                         lt_to_open = Some(0);
                     } else {
@@ -1409,7 +1927,7 @@ pub fn process(
                             if op_num == 71 {
                                 *output = Net::wrap(Box::new(An(TrigReset::new(net))));
                             } else {
-                                *output = Net::wrap(Box::new(An(ResetV::new(net))));
+                                *output = Net::wrap(Box::new(An(ResetV::new(net, access.sample_rate.0 as f32))));
                             }
                             lt_to_open = Some(0);
                         }
@@ -1445,9 +1963,10 @@ pub fn process(
                             nets.push(net.clone());
                         }
                     }
+                    let sr = access.sample_rate.0 as f32;
                     let n = &mut access.net_query.get_mut(*id).unwrap().0;
                     if op_num == 73 {
-                        *n = Net::wrap(Box::new(An(Seq::new(nets))));
+                        *n = Net::wrap(Box::new(An(Seq::new(nets, sr))));
                     } else {
                         *n = Net::wrap(Box::new(An(Select::new(nets))));
                     }
@@ -1461,7 +1980,7 @@ pub fn process(
                             let arr = &access.arr_query.get(wh.bh_parent).unwrap().0;
                             let net = &mut access.net_query.get_mut(*id).unwrap().0;
                             *net = Net::wrap(Box::new(
-                                wavech(&std::sync::Arc::new(Wave::from_samples(44100., arr)), 0, Some(0))
+                                wavech(&std::sync::Arc::new(Wave::from_samples(access.sample_rate.0, arr)), 0, Some(0))
                             ));
                             lt_to_open = Some(0);
                         }
@@ -1667,17 +2186,105 @@ pub fn process(
                     }
                 }
                 if changed || lost || op_changed {
+                    // channel offset, out(2) starts at the third channel
+                    let offset = op.get(4..)
+                        .and_then(|s| s.trim_end_matches(')').trim().parse::<usize>().ok())
+                        .unwrap_or(0);
+                    access.out_nets.0.retain(|x| x.0 != *id);
                     if let Some(input) = input {
                         let net = access.net_query.get(input).unwrap().0.clone();
-                        if net.inputs() == 0 && net.outputs() == 1 {
-                            slot.0.set(Fade::Smooth, 0.01, Box::new(net | dc(0.)));
-                        } else if net.inputs() == 0 && net.outputs() == 2 {
-                            slot.0.set(Fade::Smooth, 0.01, Box::new(net));
-                        } else {
-                            slot.0.set(Fade::Smooth, 0.01, Box::new(dc(0.) | dc(0.)));
+                        if net.inputs() == 0 && net.outputs() > 0 {
+                            access.out_nets.0.push((*id, offset, net));
+                        }
+                    }
+                    access.dac_change.send_default();
+                }
+            }
+            92 => { // rec
+                // own num keeps the recording state, so we only start/stop on change
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        if wh.link_types == (-1, 1) && wh.open {
+                            let on = access.num_query.get(wh.bh_parent).unwrap().0 != 0.;
+                            let was_on = access.num_query.get(*id).unwrap().0 != 0.;
+                            if on && !was_on {
+                                if let Some(file) = op.get(4..) {
+                                    access.record_event.send(RecordCommand(Some(file.to_string())));
+                                }
+                            } else if !on && was_on {
+                                access.record_event.send(RecordCommand(None));
+                            }
+                            if on != was_on {
+                                access.num_query.get_mut(*id).unwrap().0 = if on { 1. } else { 0. };
+                                lt_to_open = Some(-1);
+                            }
+                        }
+                    }
+                }
+            }
+            // -------------------- subpatches --------------------
+            102 => { // patch
+                let mut inputs = Vec::new();
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        if wh.open && wh.link_types.0 == 0 && wh.link_types.1 > 0 {
+                            inputs.push((wh.link_types.1, wh.bh_parent));
+                        }
+                    }
+                }
+                if let Ok(ports) = access.ports_query.get(*id) {
+                    for (k, input) in inputs {
+                        let net = access.net_query.get(input).unwrap().0.clone();
+                        for (_, inlet) in ports.inlets.iter().filter(|x| x.0 == k) {
+                            access.net_query.get_mut(*inlet).unwrap().0 = net.clone();
+                            for hole in &holes_query.get(*inlet).unwrap().0 {
+                                if let Ok(bh) = black_hole_query.get(*hole) {
+                                    if let Ok(mut wh) = white_hole_query.get_mut(bh.wh) {
+                                        if wh.link_types.0 == 0 { wh.open = true; }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            103 => {} // inlet (set by the patch circle)
+            104 => { // outlet
+                let op_changed = access.op_changed_query.get(*id).unwrap().0;
+                let lost = access.lost_wh_query.get(*id).unwrap().0;
+                let mut num_changed = false;
+                let mut net_changed = false;
+                for hole in holes {
+                    if let Ok(wh) = white_hole_query.get(*hole) {
+                        if wh.open && wh.link_types.1 == -1 { num_changed = true; }
+                        if wh.open && wh.link_types == (0, 1) { net_changed = true; }
+                    }
+                }
+                let parent = access.internal_query.get(*id).map_or(*id, |x| x.0);
+                let mut lts = Vec::new();
+                if let (Ok(ports), Some(k)) = (access.ports_query.get(parent), port_num(op)) {
+                    if num_changed { lts.push(k); }
+                    if net_changed || lost || op_changed {
+                        // all the outlets' audio stacked in order
+                        let mut graph = Net::new(0,0);
+                        for (_, outlet) in &ports.outlets {
+                            for hole in &holes_query.get(*outlet).unwrap().0 {
+                                if let Ok(wh) = white_hole_query.get(*hole) {
+                                    if wh.link_types == (0, 1) && graph.size() < access.node_limit.0 {
+                                        graph = graph | access.net_query.get(wh.bh_parent).unwrap().0.clone();
+                                    }
+                                }
+                            }
+                        }
+                        access.net_query.get_mut(parent).unwrap().0 = graph;
+                        lts.push(0);
+                    }
+                }
+                for hole in &holes_query.get(parent).unwrap().0 {
+                    if let Ok(bh) = black_hole_query.get(*hole) {
+                        if let Ok(mut wh) = white_hole_query.get_mut(bh.wh) {
+                            if lts.contains(&wh.link_types.0) { wh.open = true; }
                         }
-                    } else {
-                        slot.0.set(Fade::Smooth, 0.01, Box::new(dc(0.) | dc(0.)));
                     }
                 }
             }
@@ -1705,3 +2312,98 @@ pub fn process(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loop_queue(world: &mut World, queue: Vec<Vec<Entity>>) -> Vec<(Entity, usize)> {
+        world.insert_resource(Queue(queue));
+        let mut schedule = Schedule::default();
+        schedule.add_systems(prepare_loop_queue);
+        schedule.run(world);
+        world.resource::<LoopQueue>().0.clone()
+    }
+
+    #[test]
+    fn nested_process_with_an_order_runs_once() {
+        let mut world = World::new();
+        world.init_resource::<LoopQueue>();
+        world.insert_resource(StatusChannel(crossbeam_channel::unbounded()));
+        let c = world.spawn((Op("sum".to_string()), Holes(Vec::new()), Targets(Vec::new()))).id();
+        let inner = world.spawn((Op("process 3".to_string()), Holes(Vec::new()), Targets(vec![c]))).id();
+        let outer = world.spawn((Op("process 2".to_string()), Holes(Vec::new()), Targets(vec![inner]))).id();
+        // inner runs 3 times for each of the 2 times outer runs, and not again on its own
+        let expected = vec![(outer, 8), (inner, 3), (c, 0), (c, 0), (c, 0), (inner, 3), (c, 0), (c, 0), (c, 0)];
+        assert_eq!(loop_queue(&mut world, vec![vec![outer], vec![inner]]), expected);
+        // (whichever order they have)
+        assert_eq!(loop_queue(&mut world, vec![vec![inner], vec![outer]]), expected);
+        // process circles that are only inside each other still run (once)
+        world.get_mut::<Targets>(inner).unwrap().0.push(outer);
+        let q = loop_queue(&mut world, vec![vec![outer], vec![inner]]);
+        assert_eq!(q[0], (outer, q.len() - 1));
+    }
+
+    #[test]
+    fn late_flag_clears_when_the_connection_goes() {
+        let mut world = World::new();
+        world.init_resource::<LateCircles>();
+        world.insert_resource(StatusChannel(crossbeam_channel::unbounded()));
+        world.init_resource::<Events<OrderChange>>();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(check_order);
+        // a (order 2) -> b (order 1), so b gets its input late
+        let a = world.spawn((Order(2), Holes(Vec::new()))).id();
+        let b = world.spawn((Order(1), Holes(Vec::new()))).id();
+        let bh = world.spawn(BlackHole { wh: Entity::PLACEHOLDER, wh_parent: b }).id();
+        let wh = world.spawn(WhiteHole { bh, bh_parent: a, link_types: (-1, 1), open: true }).id();
+        world.get_mut::<BlackHole>(bh).unwrap().wh = wh;
+        world.get_mut::<Holes>(a).unwrap().0.push(bh);
+        world.get_mut::<Holes>(b).unwrap().0.push(wh);
+        schedule.run(&mut world);
+        assert!(world.resource::<LateCircles>().0.contains(&b));
+        // deleting just the connection (no order changes)
+        world.despawn(bh);
+        world.despawn(wh);
+        world.get_mut::<Holes>(a).unwrap().0.clear();
+        world.get_mut::<Holes>(b).unwrap().0.clear();
+        schedule.run(&mut world);
+        assert!(world.resource::<LateCircles>().0.is_empty());
+        // nothing changed, so it doesn't run
+        world.insert_resource(LateCircles(HashSet::from_iter([a])));
+        schedule.run(&mut world);
+        assert!(world.resource::<LateCircles>().0.contains(&a));
+        // adding a target is an edge too
+        world.entity_mut(a).insert(Targets(vec![b]));
+        schedule.run(&mut world);
+        assert_eq!(world.resource::<LateCircles>().0, HashSet::from_iter([b]));
+    }
+
+    #[test]
+    fn components_with_a_loop() {
+        let e: Vec<Entity> = (0..6).map(Entity::from_raw).collect();
+        // 1 -> 2 <-> 3 -> 4, 5 alone, 0 feeds itself
+        let edges = [(e[1], e[2]), (e[2], e[3]), (e[3], e[2]), (e[3], e[4]), (e[0], e[0])];
+        let components = order_components(&e, &edges);
+        assert_eq!(components.iter().map(|c| c.len()).sum::<usize>(), 6);
+        let mut loops: Vec<Vec<Entity>> = components.iter().filter(|c| c.len() > 1).cloned().collect();
+        loops[0].sort();
+        assert_eq!(loops, vec![vec![e[2], e[3]]]);
+        // sources first
+        let pos = |x: Entity| components.iter().position(|c| c.contains(&x)).unwrap();
+        assert!(pos(e[1]) < pos(e[2]));
+        assert_eq!(pos(e[2]), pos(e[3]));
+        assert!(pos(e[3]) < pos(e[4]));
+    }
+
+    #[test]
+    fn components_of_a_long_chain() {
+        // deep enough to overflow the stack if it were recursive
+        let e: Vec<Entity> = (0..100000).map(Entity::from_raw).collect();
+        let edges: Vec<(Entity, Entity)> = e.windows(2).map(|w| (w[1], w[0])).collect();
+        let components = order_components(&e, &edges);
+        assert_eq!(components.len(), e.len());
+        assert_eq!(components[0], vec![e[e.len() - 1]]);
+        assert_eq!(components[e.len() - 1], vec![e[0]]);
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::{
+    prelude::*,
+    scene::serde::SceneDeserializer,
+    asset::ron::Deserializer,
+};
+use serde::de::DeserializeSeed;
+
+#[cfg(not(target_arch = "wasm32"))]
+use {crossbeam_channel::Sender, std::path::PathBuf};
+
+use crate::patch::patch_to_scene;
+
+// (first version that doesn't need it, migration)
+// scenes saved with an older version get it applied, so a migration
+// has to be harmless on a scene that doesn't need it
+const MIGRATIONS: &[((u32, u32, u32), fn(String) -> String)] = &[
+    ((0, 6, 1), rename_num_push),
+];
+
+fn rename_num_push(scene: String) -> String {
+    rename_op(scene, "num_push", "push_num")
+}
+
+// rename an op (with or without arguments)
+fn rename_op(scene: String, old: &str, new: &str) -> String {
+    if !scene.trim_start().starts_with('(') {
+        let lines: Vec<String> = scene.lines().map(|line| rename_patch_op(line, old, new)).collect();
+        return lines.join("\n") + "\n";
+    }
+    let key = "\"quartz::components::Op\": (\"";
+    let mut scene = scene;
+    for end in ["\"", "(", " "] {
+        scene = scene.replace(&format!("{}{}{}", key, old, end), &format!("{}{}{}", key, new, end));
+    }
+    scene
+}
+
+// same thing in a patch line (id "op" ... or id op ...)
+fn rename_patch_op(line: &str, old: &str, new: &str) -> String {
+    if line.starts_with('#') { return line.to_string(); }
+    let Some((id, rest)) = line.split_once(' ') else { return line.to_string() };
+    let (quote, op) = match rest.strip_prefix('"') {
+        Some(op) => ("\"", op),
+        None => ("", rest),
+    };
+    match op.strip_prefix(old) {
+        Some(after) if after.is_empty() || after.starts_with(['"', '(', ' ']) => {
+            format!("{} {}{}{}", id, quote, new, after)
+        }
+        _ => line.to_string(),
+    }
+}
+
+fn parse_version(s: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = s.split('.').map(|x| x.parse::<u32>());
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(a)), Some(Ok(b)), Some(Ok(c))) => Some((a, b, c)),
+        _ => None,
+    }
+}
+
+// the version a scene was saved with (none if it doesn't say)
+pub fn scene_version(scene: &str) -> Option<(u32, u32, u32)> {
+    // patches have it in the first line
+    if let Some(rest) = scene.trim_start().strip_prefix("# quartz patch (") {
+        return parse_version(rest.split(')').next()?);
+    }
+    let key = "\"quartz::components::Version\": (\"";
+    let start = scene.find(key)? + key.len();
+    let version = scene[start..].split([' ', '"']).next()?;
+    parse_version(version)
+}
+
+// upgrade an older scene, returns the scene and a warning if there's any
+pub fn migrate(scene: String) -> (String, Option<String>) {
+    let current = parse_version(env!("CARGO_PKG_VERSION")).unwrap();
+    let Some(version) = scene_version(&scene) else { return (scene, None) };
+    if version > current {
+        let warning = format!(
+            "this scene is from a newer version ({}.{}.{}), some of it might not load",
+            version.0, version.1, version.2,
+        );
+        return (scene, Some(warning));
+    }
+    let mut scene = scene;
+    for (first, migration) in MIGRATIONS {
+        if version < *first {
+            scene = migration(scene);
+        }
+    }
+    (scene, None)
+}
+
+// ron scenes start with a (, anything else is the text format
+pub fn deserialize_scene(type_registry: &AppTypeRegistry, scene: &str) -> Result<DynamicScene, String> {
+    if !scene.trim_start().starts_with('(') {
+        return patch_to_scene(scene);
+    }
+    let mut deserializer = Deserializer::from_str(scene).map_err(|e| e.to_string())?;
+    let scene_deserializer = SceneDeserializer {
+        type_registry: &type_registry.read(),
+    };
+    scene_deserializer.deserialize(&mut deserializer)
+        .map_err(|e| format!("not a valid scene: {}", e))
+}
+
+// read a scene file in the background and send it to be spawned like a paste
+#[cfg(not(target_arch = "wasm32"))]
+pub fn open_scene(path: PathBuf, scene_sender: Sender<String>, status_sender: Sender<String>) {
+    bevy::tasks::IoTaskPool::get().spawn(async move {
+        match std::fs::read_to_string(&path) {
+            Ok(string) => { let _ = scene_sender.send(string); }
+            Err(e) => {
+                let _ = status_sender.send(format!("couldn't open {}: {}", path.display(), e));
+            }
+        }
+    }).detach();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ron(version: &str, op: &str) -> String {
+        format!(
+            "(resources: {{\"quartz::components::Version\": (\"{}\")}}, \
+            entities: {{1: (components: {{\"quartz::components::Op\": (\"{}\")}})}})",
+            version, op,
+        )
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(scene_version(&ron("0.5.2", "sum")), Some((0, 5, 2)));
+        assert_eq!(scene_version("# quartz patch (0.6.1)\n0 \"sum\"\n"), Some((0, 6, 1)));
+        assert_eq!(scene_version("0 sum\n"), None);
+        assert_eq!(parse_version("1.2"), None);
+    }
+
+    #[test]
+    fn rename_num_push_in_old_scenes() {
+        let (scene, warning) = migrate(ron("0.5.0", "num_push"));
+        assert!(warning.is_none());
+        assert!(scene.contains("Op\": (\"push_num\")"));
+        // with arguments, and not other ops that start the same
+        assert!(rename_num_push(ron("0.5.0", "num_push 3")).contains("(\"push_num 3\")"));
+        assert!(rename_num_push(ron("0.5.0", "num_pushy")).contains("(\"num_pushy\")"));
+        let patch = "# quartz patch (0.6.0)\n0 num_push n=1\n1 \"num_push\"\n2 \"num_pushy\"\n0.1 -> 1.1\n";
+        let (patch, _) = migrate(patch.to_string());
+        assert_eq!(patch, "# quartz patch (0.6.0)\n0 push_num n=1\n1 \"push_num\"\n2 \"num_pushy\"\n0.1 -> 1.1\n");
+    }
+
+    #[test]
+    fn current_and_newer_scenes_are_left_alone() {
+        let current = ron(env!("CARGO_PKG_VERSION"), "num_push");
+        assert_eq!(migrate(current.clone()), (current, None));
+        let newer = ron("99.0.0", "num_push");
+        let (scene, warning) = migrate(newer.clone());
+        assert_eq!(scene, newer);
+        assert!(warning.is_some());
+    }
+}
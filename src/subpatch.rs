@@ -0,0 +1,131 @@
+use bevy::{
+    prelude::*,
+    utils::HashSet,
+};
+use std::{fs, path::{Path, PathBuf}, time::SystemTime};
+
+use crate::{components::*, functions::*, scene::*};
+
+// how many patch circles deep a patch circle can be
+const MAX_DEPTH: usize = 8;
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// (re)load the internals of patch circles when their op or file changes
+pub fn load_subpatches(
+    mut commands: Commands,
+    changed_query: Query<(Entity, &Op), Changed<Op>>,
+    subpatch_query: Query<(Entity, &Subpatch)>,
+    internal_query: Query<(Entity, &Internal)>,
+    arrow_query: Query<&ConnectionArrow>,
+    holes_query: Query<&Holes>,
+    type_registry: Res<AppTypeRegistry>,
+    mut scenes: ResMut<Assets<DynamicScene>>,
+    status: Res<StatusChannel>,
+    time: Res<Time>,
+    mut timer: Local<f32>,
+    mut order_change: EventWriter<OrderChange>,
+) {
+    let mut to_load: Vec<(Entity, PathBuf)> = Vec::new();
+    for (e, op) in changed_query.iter() {
+        if str_to_op_num(&op.0) == 102 {
+            let path = scene_path(op.0.get(6..).unwrap_or_default().trim());
+            if subpatch_query.get(e).map_or(true, |(_, s)| s.path != path) {
+                to_load.push((e, path));
+            }
+        } else if subpatch_query.contains(e) {
+            // its internals get despawned below
+            commands.entity(e).remove::<(Subpatch, Ports)>();
+        }
+    }
+    // check the files once a second
+    *timer += time.delta_seconds();
+    if *timer > 1. {
+        *timer = 0.;
+        for (e, subpatch) in subpatch_query.iter() {
+            if modified(&subpatch.path) != subpatch.modified && !to_load.iter().any(|x| x.0 == e) {
+                to_load.push((e, subpatch.path.clone()));
+            }
+        }
+    }
+
+    let mut despawned = HashSet::new();
+    // a circle with its holes and their arrows
+    let mut despawn = |e: Entity, commands: &mut Commands| {
+        let holes = holes_query.get(e).map(|h| h.0.clone()).unwrap_or_default();
+        for e in std::iter::once(e).chain(holes) {
+            if despawned.insert(e) {
+                if let Ok(arrow) = arrow_query.get(e) {
+                    commands.entity(arrow.0).despawn();
+                }
+                commands.entity(e).despawn();
+            }
+        }
+    };
+    // internals of deleted patch circles (or ones that aren't patch circles anymore)
+    for (e, internal) in internal_query.iter() {
+        if !subpatch_query.contains(internal.0) {
+            despawn(e, &mut commands);
+        }
+    }
+    for (parent, path) in to_load {
+        for (e, internal) in internal_query.iter() {
+            if internal.0 == parent { despawn(e, &mut commands); }
+        }
+        commands.entity(parent).insert((
+            Subpatch { path: path.clone(), modified: modified(&path) },
+            Ports::default(),
+        ));
+        let mut depth = 0;
+        let mut e = parent;
+        while let Ok((_, internal)) = internal_query.get(e) {
+            e = internal.0;
+            depth += 1;
+        }
+        if depth >= MAX_DEPTH {
+            let _ = status.0.0.send(format!("patch circles can't be more than {} deep", MAX_DEPTH));
+            continue;
+        }
+        let scene = fs::read_to_string(&path)
+            .map_err(|e| format!("couldn't open {}: {}", path.display(), e))
+            .and_then(|s| deserialize_scene(&type_registry, &migrate(s).0));
+        match scene {
+            Ok(mut scene) => {
+                // don't touch the theme
+                scene.resources.clear();
+                commands.spawn((
+                    DynamicSceneBundle { scene: scenes.add(scene), ..default() },
+                    SubpatchScene(parent),
+                ));
+            }
+            Err(e) => { let _ = status.0.0.send(e); }
+        }
+    }
+    if !despawned.is_empty() { order_change.send_default(); }
+}
+
+// find the inlet and outlet circles of each patch circle
+pub fn update_ports(
+    mut ports_query: Query<&mut Ports>,
+    internal_query: Query<(Entity, &Internal, &Op, &OpNum)>,
+) {
+    for mut ports in ports_query.iter_mut() {
+        ports.inlets.clear();
+        ports.outlets.clear();
+    }
+    for (e, internal, op, op_num) in internal_query.iter() {
+        let Ok(mut ports) = ports_query.get_mut(internal.0) else { continue };
+        let Some(k) = port_num(&op.0) else { continue };
+        match op_num.0 {
+            103 => ports.inlets.push((k, e)),
+            104 => ports.outlets.push((k, e)),
+            _ => {}
+        }
+    }
+    // audio from the outlets is stacked in this order
+    for mut ports in ports_query.iter_mut() {
+        ports.outlets.sort_by_key(|x| x.0);
+    }
+}